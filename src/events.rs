@@ -1,28 +1,399 @@
-use std::str::FromStr;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
+use crate::clock::Clock;
+use crate::config::{CalendarSource, ClockFormat, Config, DedupKey, redact_url};
 use crate::types::Error;
-use anyhow::anyhow;
+use anyhow::{Context, anyhow};
 use cached::proc_macro::cached;
-use chrono::{DateTime, Datelike, Days, Local, NaiveDate, TimeZone, Utc};
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveTime, TimeZone, Utc};
 use chrono_tz::Tz;
+use futures_util::StreamExt;
 use icalendar::{
     Calendar, CalendarComponent, CalendarDateTime, Component, DatePerhapsTime, EventLike,
 };
 use reqwest::StatusCode;
 use rrule::RRuleSet;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
-use std::time::Duration;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Semaphore, broadcast};
 use warp::{Filter, Reply, filters::BoxedFilter, reject};
 
-async fn fetch_calendar(calendar_url: &str) -> anyhow::Result<String> {
-    let calendar_request = reqwest::get(calendar_url).await?;
-    let calendar_data = calendar_request.text().await?;
+/// Rewrites `webcal://` and `webcals://` subscription links into URLs fetchable over http(s)
+fn normalize_calendar_url(calendar_url: &str) -> String {
+    let (scheme, rest) = match calendar_url.split_once("://") {
+        Some(parts) => parts,
+        None => return calendar_url.to_string(),
+    };
+    match scheme.to_ascii_lowercase().as_str() {
+        // Calendar clients fetch plain webcal links over https as well
+        "webcal" | "webcals" => format!("https://{rest}"),
+        _ => calendar_url.to_string(),
+    }
+}
+
+/// Limits concurrent calendar fetches so that a traffic spike on a cold cache
+/// queues up instead of hammering the upstream, set by `init`
+static CALENDAR_FETCH_PERMITS: OnceLock<Semaphore> = OnceLock::new();
+
+/// Client for upstream requests, honoring `HTTPS_PROXY`/`HTTP_PROXY` unless overridden by the configured proxy, set by `init`
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Sets up the state shared by all requests from the validated configuration, once at startup
+/// before any calendar is fetched
+pub fn init(config: &Config) {
+    let mut builder = reqwest::Client::builder();
+    // The proxy has been validated along with the rest of the configuration
+    if let Some(proxy) = config.proxy.as_deref().map(reqwest::Proxy::all) {
+        builder = builder.proxy(proxy.expect("invalid proxy"));
+    }
+    let initialized = HTTP_CLIENT.set(builder.build().unwrap_or_default()).is_ok()
+        && CALENDAR_FETCH_PERMITS
+            .set(Semaphore::new(config.fetch_concurrency))
+            .is_ok()
+        && CACHE_TTL.set(config.cache_ttl).is_ok();
+    assert!(initialized, "events::init called more than once");
+}
+
+fn http_client() -> &'static reqwest::Client {
+    HTTP_CLIENT.get().expect("events::init not called")
+}
+
+/// Previously fetched calendar along with the validators for conditional requests
+#[derive(Clone)]
+struct UpstreamCalendar {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    calendar_data: String,
+}
+
+/// Upstream calendars by URL, revalidated instead of downloaded again on each refresh
+static UPSTREAM_CALENDARS: LazyLock<Mutex<HashMap<String, UpstreamCalendar>>> =
+    LazyLock::new(Default::default);
+
+/// Fetches the calendar, returning it along with whether it changed since the previous fetch
+async fn fetch_calendar(calendar_url: &str) -> anyhow::Result<(String, bool)> {
+    // Local files are read as is, e.g. for development without network access
+    if let Some(path) = calendar_url.strip_prefix("file://") {
+        let calendar_bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("reading {path} failed"))?;
+        return Ok((
+            non_empty_calendar(decode_calendar(&calendar_bytes, None))?,
+            true,
+        ));
+    }
+    let calendar_url = normalize_calendar_url(calendar_url);
+    let previous = UPSTREAM_CALENDARS
+        .lock()
+        .ok()
+        .and_then(|upstream| upstream.get(&calendar_url).cloned());
+    let mut request = http_client().get(&calendar_url);
+    if let Some(previous) = &previous {
+        if let Some(etag) = &previous.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &previous.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let permits = CALENDAR_FETCH_PERMITS
+        .get()
+        .expect("events::init not called");
+    let _permit = permits.acquire().await?;
+    // Errors leave out the URL, which may hold a secret token and ends up in `/health`
+    let calendar_request = request.send().await.map_err(reqwest::Error::without_url)?;
+    if calendar_request.status() == reqwest::StatusCode::NOT_MODIFIED
+        && let Some(previous) = previous
+    {
+        return Ok((previous.calendar_data, false));
+    }
+    let header = |name| {
+        calendar_request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let content_type = header(reqwest::header::CONTENT_TYPE);
+    let etag = header(reqwest::header::ETAG);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+    let calendar_bytes = calendar_request
+        .bytes()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    let calendar_data =
+        non_empty_calendar(decode_calendar(&calendar_bytes, content_type.as_deref()))?;
+    if let Ok(mut upstream) = UPSTREAM_CALENDARS.lock() {
+        upstream.insert(
+            calendar_url,
+            UpstreamCalendar {
+                etag,
+                last_modified,
+                calendar_data: calendar_data.clone(),
+            },
+        );
+    }
+    Ok((calendar_data, true))
+}
+
+/// Rejects empty responses, which the upstream transiently returns, instead of
+/// passing them on as calendars without events
+fn non_empty_calendar(calendar_data: String) -> anyhow::Result<String> {
+    if calendar_data.trim().is_empty() {
+        return Err(anyhow!("the calendar response was empty"));
+    }
     Ok(calendar_data)
 }
 
+/// Decodes the calendar in the charset declared in its `Content-Type`, defaulting to UTF-8,
+/// and strips the byte order mark some exports begin with
+fn decode_calendar(bytes: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(|content_type| {
+            content_type.split(';').find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("charset")
+                    .then(|| value.trim().trim_matches('"'))
+            })
+        })
+        .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    // A byte order mark takes precedence over the declared charset
+    let (text, _, _) = encoding.decode(bytes);
+    text.trim_start_matches('\u{feff}').to_string()
+}
+
+/// Properties by which merged events are tagged with their source calendar
+const CALENDAR_LABEL_PROPERTY: &str = "X-LINKKI-CALENDAR-LABEL";
+const CALENDAR_COLOR_PROPERTY: &str = "X-LINKKI-CALENDAR-COLOR";
+const CALENDAR_NAME_PROPERTY: &str = "X-LINKKI-CALENDAR-NAME";
+
+/// Key identifying the event across calendars, `None` when it can't be identified
+fn dedup_key(event: &icalendar::Event, key: DedupKey) -> Option<String> {
+    match key {
+        DedupKey::Uid => {
+            let uid = event.get_uid().filter(|uid| !uid.is_empty())?;
+            let recurrence_id = event.property_value("RECURRENCE-ID").unwrap_or_default();
+            Some(format!("{uid}\n{recurrence_id}"))
+        }
+        DedupKey::SummaryStart => Some(format!(
+            "{}\n{}",
+            event.get_summary()?,
+            event.property_value("DTSTART")?
+        )),
+    }
+}
+
+/// Drops the events with the given UIDs, before any further processing
+fn remove_hidden_events(calendar: &mut Calendar, hidden_uids: &HashSet<String>) {
+    calendar.components.retain(|component| match component {
+        CalendarComponent::Event(event) => {
+            !event.get_uid().is_some_and(|uid| hidden_uids.contains(uid))
+        }
+        _ => true,
+    });
+}
+
+/// Moves the events of the calendar into the merged calendar, tagged with the source.
+/// Events already merged from a previous, higher priority calendar are dropped, their keys
+/// being collected in `seen`. Calendar level properties are kept from the first calendar providing them.
+fn merge_calendar(
+    merged: &mut Calendar,
+    calendar: Calendar,
+    source: &CalendarSource,
+    seen: &mut HashSet<String>,
+    key: DedupKey,
+) {
+    for property in calendar.properties {
+        if merged.property_value(property.key()).is_none() {
+            merged.append_property(property);
+        }
+    }
+    // Events of a single calendar are never duplicates of each other, e.g. overridden occurrences
+    let mut merged_keys = HashSet::new();
+    for component in calendar.components {
+        if let CalendarComponent::Event(mut event) = component {
+            if let Some(event_key) = dedup_key(&event, key) {
+                if seen.contains(&event_key) {
+                    continue;
+                }
+                merged_keys.insert(event_key);
+            }
+            if let Some(label) = &source.label {
+                event.add_property(CALENDAR_LABEL_PROPERTY, label);
+            }
+            if let Some(color) = &source.color {
+                event.add_property(CALENDAR_COLOR_PROPERTY, color);
+            }
+            if let Some(name) = &source.name {
+                event.add_property(CALENDAR_NAME_PROPERTY, name);
+            }
+            merged.push(event);
+        }
+    }
+    seen.extend(merged_keys);
+}
+
+/// Properties which may have alternatives in multiple languages
+const LOCALIZED_PROPERTIES: [&str; 3] = ["SUMMARY", "DESCRIPTION", "LOCATION"];
+
+/// Name under which the alternative of the property in the given language is kept
+fn localized_property(name: &str, language: &str) -> String {
+    // Match languages by their primary subtag, e.g. `fi-FI` as `fi`
+    let language = language.split('-').next().unwrap_or(language);
+    format!("X-LINKKI-{name}-{}", language.to_ascii_uppercase())
+}
+
+/// Copies properties with a `LANGUAGE` parameter under language specific names,
+/// as only one property of each name survives parsing into an event
+fn keep_localized_alternatives(component: &mut icalendar::parser::Component) {
+    let alternatives: Vec<icalendar::parser::Property> = component
+        .properties
+        .iter()
+        .filter(|property| LOCALIZED_PROPERTIES.contains(&property.name.as_str()))
+        .filter_map(|property| {
+            let language = property
+                .params
+                .iter()
+                .find(|param| param.key.as_str().eq_ignore_ascii_case("LANGUAGE"))?
+                .val
+                .as_ref()?;
+            Some(icalendar::parser::Property {
+                name: localized_property(property.name.as_str(), language.as_str()).into(),
+                val: property.val.clone(),
+                params: vec![],
+            })
+        })
+        .collect();
+    component.properties.extend(alternatives);
+}
+
+/// Splits comma separated `CATEGORIES` values into a property per category. The parser
+/// unescapes `\,` of category names, which can't be told apart from separators afterwards.
+fn split_categories(unfolded: &str) -> String {
+    let mut result = String::with_capacity(unfolded.len());
+    for line in unfolded.split_inclusive('\n') {
+        let is_categories = line.get(..11).is_some_and(|start| {
+            start.eq_ignore_ascii_case("CATEGORIES:") || start.eq_ignore_ascii_case("CATEGORIES;")
+        });
+        // Parameter values may contain colons when quoted
+        let mut quoted = false;
+        let value_start = is_categories
+            .then(|| {
+                line.find(|c| {
+                    quoted ^= c == '"';
+                    c == ':' && !quoted
+                })
+            })
+            .flatten();
+        let Some(value_start) = value_start else {
+            result.push_str(line);
+            continue;
+        };
+        let (name, rest) = line.split_at(value_start + 1);
+        let value = rest.trim_end_matches(['\r', '\n']);
+        // The split properties need line breaks between them even at the end of the data
+        let line_ending = match &rest[value.len()..] {
+            "" => "\r\n",
+            line_ending => line_ending,
+        };
+
+        let mut categories = vec![String::new()];
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let current = categories.last_mut().unwrap();
+                    current.push(c);
+                    current.extend(chars.next());
+                }
+                ',' => categories.push(String::new()),
+                _ => categories.last_mut().unwrap().push(c),
+            }
+        }
+        for category in categories
+            .iter()
+            .filter(|category| !category.trim().is_empty())
+        {
+            result.push_str(name);
+            result.push_str(category);
+            result.push_str(line_ending);
+        }
+    }
+    result
+}
+
+/// Properties whose original lines are kept for `?raw_times=true`
+const RAW_TIME_PROPERTIES: [&str; 2] = ["DTSTART", "DTEND"];
+
+/// Name under which the original line of the property is kept
+fn raw_time_property(name: &str) -> String {
+    format!("X-LINKKI-{name}-RAW")
+}
+
+/// Copies the `DTSTART` and `DTEND` lines of events as they are, parameters included, since
+/// parsing converts their values and drops their parameter quoting
+fn keep_raw_times(unfolded: &str) -> String {
+    let mut result = String::with_capacity(unfolded.len());
+    let mut components: Vec<&str> = vec![];
+    for line in unfolded.split_inclusive('\n') {
+        result.push_str(line);
+        let content = line.trim_end_matches(['\r', '\n']);
+        let (name, rest) = content
+            .find([':', ';'])
+            .map_or((content, ""), |index| content.split_at(index));
+        if name.eq_ignore_ascii_case("BEGIN") {
+            components.push(rest.get(1..).unwrap_or_default());
+        } else if name.eq_ignore_ascii_case("END") {
+            components.pop();
+        } else if components
+            .last()
+            .is_some_and(|component| component.eq_ignore_ascii_case("VEVENT"))
+            && let Some(name) = RAW_TIME_PROPERTIES
+                .iter()
+                .find(|property| property.eq_ignore_ascii_case(name))
+        {
+            if !line.ends_with('\n') {
+                result.push_str("\r\n");
+            }
+            result.push_str(&raw_time_property(name));
+            result.push(':');
+            result.push_str(content);
+            result.push_str(&line[content.len()..]);
+        }
+    }
+    result
+}
+
 fn process_calendar(calendar_data: String) -> anyhow::Result<Calendar> {
-    Calendar::from_str(&calendar_data).map_err(|a| anyhow!(a))
+    let unfolded = keep_raw_times(&split_categories(&icalendar::parser::unfold(
+        &calendar_data,
+    )));
+    let mut parsed = icalendar::parser::read_calendar(&unfolded).map_err(|a| anyhow!(a))?;
+    for component in parsed.components.iter_mut() {
+        if component.name.as_str() == "VEVENT" {
+            keep_localized_alternatives(component);
+        }
+    }
+    Ok(Calendar::from(parsed))
+}
+
+/// Value of the property, preferring its alternative in the given language when one exists
+fn localized_value<'a>(
+    event: &'a icalendar::Event,
+    name: &str,
+    language: Option<&str>,
+) -> Option<&'a str> {
+    language
+        .and_then(|language| event.property_value(&localized_property(name, language)))
+        .or_else(|| event.property_value(name))
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -31,18 +402,94 @@ struct Location {
     url: String,
 }
 
-#[skip_serializing_none]
+/// Start or end of an event in the requested time format
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+enum Timestamp {
+    Formatted(String),
+    EpochMillis(i64),
+}
+
+/// Unambiguous start and end of an event
+#[derive(Serialize, Clone, Debug)]
+struct When {
+    /// iso8601 date for all day events, otherwise RFC 3339 timestamp with the local offset,
+    /// or milliseconds since the Unix epoch
+    start: Timestamp,
+    /// Exclusive end, formatted like `start`
+    end: Timestamp,
+    all_day: bool,
+    /// IANA name of the timezone of the timestamps, `None` for all day events
+    timezone: Option<String>,
+}
+
+/// Event as returned by the API. Missing optional values are serialized as `null`
+/// instead of being omitted, so that every event has the same set of fields.
 #[derive(Serialize, Clone, Debug)]
 struct Event {
+    /// Stable identifier of the event or occurrence, always present
+    id: String,
     summary: String,
-    date: String,
+    /// Human readable start and end, deprecated in favor of `when`
+    date: Option<String>,
+    when: When,
     start_iso8601: String,
     end_iso8601: String,
     location: Option<Location>,
     description: Option<String>,
+    /// Whether the description was shortened, so that the full one can be requested
+    description_truncated: bool,
+    color: Option<String>,
+    /// Whether the event has started but not yet ended
+    in_progress: bool,
+    /// Whether the event ends on a later local day than it starts on
+    multi_day: bool,
+    /// Length of the event in minutes, 0 for points in time
+    duration_minutes: i64,
+    /// Whole days from today to the day the event starts on, negative for past events
+    days_until: i64,
+    /// Whether the event is an occurrence of a recurring series
+    recurring: bool,
+    /// Original start timestamp of the occurrence within its series
+    recurrence_id: Option<String>,
+    /// Recurrence rule of unexpanded recurring events
+    rrule: Option<String>,
+    /// Creation timestamp of the event
+    created: Option<String>,
+    /// Label of the calendar the event originates from
+    calendar_label: Option<String>,
+    /// Color of the calendar the event originates from
+    calendar_color: Option<String>,
+    /// iCal properties not otherwise mapped to fields, when requested
+    raw_properties: Option<BTreeMap<String, String>>,
+    /// Original `DTSTART` line of the event or its series, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dtstart_raw: Option<String>,
+    /// Original `DTEND` line of the event or its series, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dtend_raw: Option<String>,
+    /// Web page of the event, from the `URL` property or, when enabled, the description
+    link: Option<String>,
+    /// Links to files attached to the event, omitted when there are none
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<String>,
+    /// Whether the event is highlighted, by its category or `X-FEATURED` property
+    featured: bool,
+    /// Local day on which the event starts
+    #[serde(skip)]
+    start_day: NaiveDate,
+    /// Last local day on which the event takes place, events ending at midnight ending on the previous day
+    #[serde(skip)]
+    last_day: NaiveDate,
+    /// Web page of the event, from the iCal `URL` property
+    #[serde(skip)]
+    url: Option<String>,
+    /// Categories of the event, from the iCal `CATEGORIES` property
+    #[serde(skip)]
+    categories: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum EventDate {
     Date(NaiveDate),
     DateTimeUtc(DateTime<Utc>),
@@ -63,11 +510,16 @@ fn to_event_date(datetime: DatePerhapsTime) -> Option<EventDate> {
                 // Skip if timezone is not found
                 _ => return None,
             };
-            // TODO: Remove unwraps
-            let date_time: DateTime<Tz> = tz.from_local_datetime(&naive_date_time).unwrap();
-            let date_time_utc: DateTime<Utc> =
-                DateTime::<Utc>::from_timestamp(date_time.timestamp(), 0).unwrap();
-            Some(EventDate::DateTimeUtc(date_time_utc))
+            // Ambiguous local times resolve to the earlier instant, and times skipped
+            // by a DST transition are shifted forward past it
+            let date_time: DateTime<Tz> = tz
+                .from_local_datetime(&naive_date_time)
+                .earliest()
+                .or_else(|| {
+                    tz.from_local_datetime(&(naive_date_time + chrono::Duration::hours(1)))
+                        .earliest()
+                })?;
+            Some(EventDate::DateTimeUtc(date_time.to_utc()))
         }
         date_perhaps_time => {
             eprintln!("Unhandled timestamp type: {:?}", date_perhaps_time);
@@ -76,6 +528,130 @@ fn to_event_date(datetime: DatePerhapsTime) -> Option<EventDate> {
     }
 }
 
+/// Formats the date as an iso8601 date, or timestamp when time is known
+fn to_iso8601(date: &EventDate) -> String {
+    match date {
+        EventDate::Date(date) => format!("{}", date.format("%Y-%m-%d")),
+        EventDate::DateTimeUtc(date_time) => {
+            date_time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, counting all day events from midnight UTC
+fn to_epoch_millis(date: &EventDate) -> i64 {
+    match date {
+        EventDate::Date(date) => date.and_time(NaiveTime::MIN).and_utc().timestamp_millis(),
+        EventDate::DateTimeUtc(date_time) => date_time.timestamp_millis(),
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across requests and builds unlike the standard library hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Identifier of the event, from its UID and the occurrence within a series,
+/// or a hash of its contents when the UID is missing
+fn event_id(
+    uid: Option<&str>,
+    recurrence_id: Option<&str>,
+    summary: &str,
+    start_iso8601: &str,
+    end_iso8601: &str,
+) -> String {
+    match (uid.filter(|uid| !uid.is_empty()), recurrence_id) {
+        (Some(uid), Some(recurrence_id)) => format!("{uid}/{recurrence_id}"),
+        (Some(uid), None) => uid.to_string(),
+        (None, _) => {
+            let contents = format!("{summary}\n{start_iso8601}\n{end_iso8601}");
+            format!("{:016x}", fnv1a(contents.as_bytes()))
+        }
+    }
+}
+
+/// Collapses all day events of the same summary whose dates touch or overlap
+/// into single multi-day events, keeping the properties of the earliest one
+fn merge_adjacent_all_day(events: Vec<icalendar::Event>) -> Vec<icalendar::Event> {
+    let mut merged = vec![];
+    let mut all_day = vec![];
+    for event in events {
+        match (
+            event.get_start().and_then(to_event_date),
+            event_end(&event).and_then(to_event_date),
+        ) {
+            (Some(EventDate::Date(start)), Some(EventDate::Date(end))) => {
+                all_day.push((start, end, event))
+            }
+            _ => merged.push(event),
+        }
+    }
+    all_day.sort_by(|(a_start, _, a), (b_start, _, b)| {
+        a.get_summary()
+            .cmp(&b.get_summary())
+            .then(a_start.cmp(b_start))
+    });
+
+    let mut current: Option<(NaiveDate, icalendar::Event)> = None;
+    for (start, end, event) in all_day {
+        match &mut current {
+            Some((current_end, current_event))
+                if current_event.get_summary() == event.get_summary() && start <= *current_end =>
+            {
+                if end > *current_end {
+                    *current_end = end;
+                    current_event.ends(end);
+                }
+            }
+            _ => merged.extend(current.replace((end, event)).map(|(_, event)| event)),
+        }
+    }
+    merged.extend(current.map(|(_, event)| event));
+    merged
+}
+
+/// End of the event, computed from `DURATION` when `DTEND` is missing as per RFC 5545
+fn event_end(event: &icalendar::Event) -> Option<DatePerhapsTime> {
+    if let Some(end) = event.get_end() {
+        return Some(end);
+    }
+    let duration = iso8601::duration(event.property_value("DURATION")?).ok()?;
+    let duration = chrono::Duration::from_std(duration.into()).ok()?;
+    // Durations beyond the supported dates leave the event without an end
+    match to_event_date(event.get_start()?)? {
+        EventDate::Date(start_date) => Some(DatePerhapsTime::Date(
+            start_date.checked_add_days(Days::new(u64::try_from(duration.num_days()).ok()?))?,
+        )),
+        EventDate::DateTimeUtc(start_time) => Some(DatePerhapsTime::DateTime(
+            start_time.checked_add_signed(duration)?.into(),
+        )),
+    }
+}
+
+/// Inclusive end of an event, the last day of all day events, whose `DTEND` is exclusive,
+/// and the end of timed events as is. Filtering, ordering and formatting all interpret the
+/// ends of events through this, rather than each adjusting exclusive ends on their own.
+fn effective_end(event: &icalendar::Event) -> Option<EventDate> {
+    let end = event_end(event).and_then(to_event_date)?;
+    Some(match event.get_start().and_then(to_event_date) {
+        Some(start) => inclusive_end(&start, end),
+        None => inclusive_end(&end, end),
+    })
+}
+
+/// Inclusive end of an event with the given start and exclusive end, see `effective_end`.
+/// All day events without a duration end on the day they start on.
+fn inclusive_end(start: &EventDate, end: EventDate) -> EventDate {
+    match (start, end) {
+        (EventDate::Date(start), EventDate::Date(end)) => {
+            EventDate::Date(end.pred_opt().unwrap_or(end).max(*start))
+        }
+        (_, end) => end,
+    }
+}
+
 #[derive(Clone)]
 struct Space {
     space_label: String,
@@ -84,7 +660,7 @@ struct Space {
 
 async fn fetch_spaces() -> anyhow::Result<String> {
     let url: &'static str = "https://navi.jyu.fi/api/spaces";
-    let request = reqwest::get(url).await?;
+    let request = http_client().get(url).send().await?;
     let text_content = request.text().await?;
     Ok(text_content)
 }
@@ -115,7 +691,31 @@ fn parse_spaces(string: String) -> anyhow::Result<Vec<Space>> {
     Ok(parsed_spaces)
 }
 
-fn url_for_location(location: &str, spaces: &Vec<Space>) -> String {
+/// Strips a leading venue name from comma separated postal addresses, which
+/// otherwise tends to confuse free text map searches
+fn clean_address(location: &str) -> String {
+    let parts: Vec<&str> = location
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+    match &parts[..] {
+        // Venue names rarely contain numbers, unlike street addresses and postal codes
+        [venue, address @ ..]
+            if address.len() >= 2 && !venue.chars().any(|c| c.is_ascii_digit()) =>
+        {
+            address.join(", ")
+        }
+        _ => parts.join(", "),
+    }
+}
+
+fn url_for_location(
+    location: &str,
+    spaces: &[Space],
+    address_cleanup: bool,
+    url_template: &str,
+) -> String {
     // navi.jyu.fi links for locations begining with university space codes (case sensitive!)
     for space in spaces {
         if location.starts_with(&space.space_label) {
@@ -123,211 +723,1009 @@ fn url_for_location(location: &str, spaces: &Vec<Space>) -> String {
         }
     }
 
-    // Link to Google Maps by default
-    format!(
-        "https://www.google.com/maps/search/?api=1&query={}",
-        urlencoding::encode(location)
-    )
+    // Cleaning up addresses is heuristic, thus opt-in
+    let query = match address_cleanup {
+        true => clean_address(location),
+        false => location.to_string(),
+    };
+
+    // Link to the configured map search, Google Maps by default
+    url_template.replace("{query}", &urlencoding::encode(&query))
 }
 
-fn data_to_events(
-    calendar: Calendar,
-    spaces: Vec<Space>,
-    current_time: DateTime<Utc>,
-) -> Result<Vec<Event>, warp::Rejection> {
-    let mut event_components: Vec<icalendar::Event> = calendar
+/// Collapses runs of whitespace, including leftovers of line folding, into single spaces
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Like `normalize_whitespace`, but keeps line breaks, dropping leading and trailing blank lines
+fn normalize_multiline(text: &str) -> String {
+    let lines: Vec<String> = text.lines().map(normalize_whitespace).collect();
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+/// Properties which are mapped to event fields
+const MAPPED_PROPERTIES: [&str; 15] = [
+    "UID",
+    "SUMMARY",
+    "DESCRIPTION",
+    "LOCATION",
+    "DTSTART",
+    "DTEND",
+    "DURATION",
+    "COLOR",
+    "RRULE",
+    "RECURRENCE-ID",
+    "CREATED",
+    "DTSTAMP",
+    "ATTACH",
+    "URL",
+    "X-FEATURED",
+];
+
+/// iCal properties of the event not mapped to event fields, values of repeated properties comma separated
+fn raw_properties(event: &icalendar::Event) -> BTreeMap<String, String> {
+    let mut raw: BTreeMap<String, String> = BTreeMap::new();
+    let properties = event.properties().iter();
+    let multi_properties = event
+        .multi_properties()
         .iter()
-        // Filter out components other than of type event
-        .flat_map(|component| match component {
-            CalendarComponent::Event(event) => vec![event],
-            _ => vec![],
+        .flat_map(|(key, properties)| properties.iter().map(move |property| (key, property)));
+    for (key, property) in properties.chain(multi_properties) {
+        // Internal properties are not part of the upstream calendar
+        if MAPPED_PROPERTIES.contains(&key.as_str()) || key.starts_with("X-LINKKI-") {
+            continue;
+        }
+        raw.entry(key.clone())
+            .and_modify(|value| {
+                value.push(',');
+                value.push_str(property.value());
+            })
+            .or_insert_with(|| property.value().to_string());
+    }
+    raw
+}
+
+/// Links attached to the event with `ATTACH`, skipping inline binary attachments
+fn event_attachments(event: &icalendar::Event) -> Vec<String> {
+    event
+        .multi_properties()
+        .get("ATTACH")
+        .into_iter()
+        .flatten()
+        .filter(|property| {
+            property.value_type() != Some(icalendar::ValueType::Binary)
+                && !property
+                    .params()
+                    .get("ENCODING")
+                    .is_some_and(|encoding| encoding.value().eq_ignore_ascii_case("BASE64"))
         })
-        // Populate recurring events
-        .flat_map(|event| {
-            // Construct a string containing only the recurrence rules of the event
-            let rrules = ["DTSTART", "RRULE", "EXRULE", "RDATE", "EXDATE"];
-            let mut ruleset_string = "".to_string();
-            for rrule in rrules {
-                match event.property_value(rrule) {
-                    Some(rule) => ruleset_string.push_str(&format!("{rrule}:{rule}\n")),
-                    None => {
-                        let multi_rules = event.multi_properties().get(rrule);
-                        if let Some(props) = multi_rules {
-                            for prop in props {
-                                ruleset_string.push_str(&format!("{rrule}:{}\n", prop.value()))
-                            }
-                        }
-                    }
-                }
-            }
+        .map(|property| property.value().trim())
+        .filter(|value| reqwest::Url::parse(value).is_ok())
+        .map(String::from)
+        .collect()
+}
 
-            // Parse recurrence rules
-            let rrule: RRuleSet = match ruleset_string.parse() {
-                // Append only the original event if parsing recurrence fails or recurrence rules don't exist
-                Err(_) => return vec![event.to_owned()],
-                Ok(rrule) => rrule,
-            };
+/// First http(s) URL in the text, without trailing punctuation
+fn first_link(text: &str) -> Option<String> {
+    let start = ["http://", "https://"]
+        .iter()
+        .filter_map(|scheme| text.find(scheme))
+        .min()?;
+    let link = text[start..]
+        .split(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\''))
+        .next()?
+        .trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+    Some(link.to_string())
+}
 
-            // Make clones of the original event with new start and end timestamps
-            const MAX_RECURRENCES: u16 = 100;
-            rrule
-                .all(MAX_RECURRENCES)
-                .dates
+/// Whether the event has the featured category or a true `X-FEATURED` property
+fn is_featured(event: &icalendar::Event, featured_category: &str) -> bool {
+    let featured_property = event
+        .property_value("X-FEATURED")
+        .is_some_and(|value| ["TRUE", "YES", "1"].contains(&value.trim().to_uppercase().as_str()));
+    featured_property
+        || event_categories(event)
+            .iter()
+            .any(|category| category.eq_ignore_ascii_case(featured_category))
+}
+
+/// Categories of the event, in the order they are listed
+fn event_categories(event: &icalendar::Event) -> Vec<String> {
+    event
+        .multi_properties()
+        .get("CATEGORIES")
+        .into_iter()
+        .flatten()
+        // Comma separated values are split into properties by `split_categories`
+        .map(|property| property.value().trim())
+        .filter(|category| !category.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Configured color of the category, or one derived from its name so that it stays the same
+fn category_color(category: &str, colors: &HashMap<String, String>) -> String {
+    let category = category.to_lowercase();
+    if let Some(color) = colors.get(&category) {
+        return color.clone();
+    }
+    // Only the hue varies, keeping the colors equally legible
+    let hue = (fnv1a(category.as_bytes()) % 360) as f64;
+    hsl_to_hex(hue, 0.6, 0.45)
+}
+
+/// Converts a color from HSL, hue in degrees and others between 0 and 1, to a `#rrggbb` hex color
+fn hsl_to_hex(hue: f64, saturation: f64, lightness: f64) -> String {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let offset = lightness - chroma / 2.0;
+    let channel = |value: f64| ((value + offset) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(r), channel(g), channel(b))
+}
+
+/// Raw color value of the event, from RFC 7986 `COLOR` or a vendor specific `X-*-COLOR` property
+fn event_color(event: &icalendar::Event) -> Option<String> {
+    event
+        .property_value("COLOR")
+        .or_else(|| {
+            event
+                .properties()
                 .iter()
-                .flat_map(|date| {
-                    let mut event_clone = event.clone();
-                    match (
-                        // TODO: Invoking to_event_date can be omitted, remove it
-                        event.get_start().map(to_event_date),
-                        event.get_end().map(to_event_date),
-                    ) {
-                        // Timestamps without time
-                        (
-                            Some(Some(EventDate::Date(original_start_date))),
-                            Some(Some(EventDate::Date(original_end_date))),
-                        ) => {
-                            let duration = Days::new(
-                                (original_end_date.num_days_from_ce()
-                                    - original_start_date.num_days_from_ce())
-                                    as u64,
-                            );
-                            let event_end = date.to_owned() + duration;
-                            event_clone.starts(DatePerhapsTime::Date(date.date_naive()));
-                            event_clone.ends(DatePerhapsTime::Date(event_end.date_naive()));
-                            vec![event_clone]
-                        }
-                        // Timestamps with time
-                        (
-                            Some(Some(EventDate::DateTimeUtc(original_start_date))),
-                            Some(Some(EventDate::DateTimeUtc(original_end_date))),
-                        ) => {
-                            let duration =
-                                original_end_date.signed_duration_since(original_start_date);
-                            let event_end = *date + duration;
-                            let event_end_utc =
-                                DateTime::<Utc>::from_timestamp(event_end.timestamp(), 0).unwrap();
-                            let event_start = date;
-                            let event_start_utc =
-                                DateTime::<Utc>::from_timestamp(event_start.timestamp(), 0)
-                                    .unwrap();
-                            event_clone.starts(DatePerhapsTime::DateTime(event_start_utc.into()));
-                            event_clone.ends(DatePerhapsTime::DateTime(event_end_utc.into()));
-                            vec![event_clone]
-                        }
-                        _ => {
-                            // Skip if event start and end are expressed in differing formats, or when parsing fails
-                            println!("warning: skipping event {:?} recurrence", event);
-                            vec![]
-                        }
-                    }
-                })
-                .collect()
+                .find(|(key, _)| key.starts_with("X-") && key.ends_with("-COLOR"))
+                .map(|(_, property)| property.value())
         })
-        // Filter past events out
-        .filter(|event| {
-            //let current_time: DateTime<Local> = Local::now();
-            match event.get_end().map(to_event_date) {
-                Some(Some(end_time)) => match end_time {
+        .map(String::from)
+}
+
+/// Time window from which events are returned
+#[derive(Debug)]
+enum EventWindow {
+    /// Events which have not ended by the given time, starting at most a year later
+    Upcoming(DateTime<Utc>),
+    /// Like `Upcoming`, but excluding events which have already started by the given time
+    NotStarted(DateTime<Utc>),
+    /// Events which have ended between the given times, most recent first
+    Past {
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    },
+    /// Events taking place on any of the days between the given dates, inclusive
+    Range { first: NaiveDate, last: NaiveDate },
+    /// Events starting between the given times, unbounded when unset
+    Between {
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        inclusive: Inclusive,
+    },
+}
+
+/// Which bounds of a `Between` window are inclusive
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Inclusive {
+    /// Half-open, so that adjacent ranges tile without overlap
+    #[default]
+    Start,
+    End,
+    Both,
+    None,
+}
+
+/// Start of the event as a point in time, all day events starting at local midnight
+fn event_start_time(event: &icalendar::Event, timezone: Tz) -> Option<DateTime<Utc>> {
+    match event.get_start().and_then(to_event_date)? {
+        EventDate::Date(date) => date
+            .and_hms_opt(0, 0, 0)?
+            .and_local_timezone(timezone)
+            .earliest()
+            .map(|start| start.to_utc()),
+        EventDate::DateTimeUtc(start) => Some(start),
+    }
+}
+
+impl EventWindow {
+    /// Whether the event is within the window, local days being those of the timezone
+    fn contains(&self, event: &icalendar::Event, timezone: Tz) -> bool {
+        let Some(end_time) = effective_end(event) else {
+            return false;
+        };
+        match self {
+            EventWindow::Upcoming(current_time) => {
+                // Filter past events out
+                let is_upcoming = match &end_time {
                     EventDate::Date(end_date) => {
-                        current_time.num_days_from_ce() < end_date.num_days_from_ce()
+                        current_time.num_days_from_ce() <= end_date.num_days_from_ce()
                     }
                     EventDate::DateTimeUtc(end_time) => {
                         current_time.timestamp() <= end_time.timestamp()
                     }
-                },
-                _ => false,
+                };
+                // Filter out events ending more than a year in the future, the effective end
+                // of all-day events being their last day
+                let max_time: DateTime<Utc> =
+                    *current_time + Duration::from_secs(365 * 24 * 60 * 60);
+                let is_within_a_year = match &end_time {
+                    EventDate::Date(end_date) => {
+                        max_time.num_days_from_ce() > end_date.num_days_from_ce()
+                    }
+                    EventDate::DateTimeUtc(end_time) => max_time.timestamp() > end_time.timestamp(),
+                };
+                is_upcoming && is_within_a_year
             }
-        })
-        // Filter out events with start timestamp more than a year in the future
-        .filter(|event| {
-            let max_time: DateTime<Utc> = current_time + Duration::from_secs(365 * 24 * 60 * 60);
-            match event.get_end().map(to_event_date) {
-                Some(Some(start_time)) => match start_time {
-                    EventDate::Date(start_date) => {
-                        max_time.num_days_from_ce() > start_date.num_days_from_ce()
+            EventWindow::NotStarted(current_time) => {
+                let is_not_started = match event.get_start().map(to_event_date) {
+                    Some(Some(EventDate::Date(start_date))) => {
+                        current_time.num_days_from_ce() < start_date.num_days_from_ce()
                     }
-                    EventDate::DateTimeUtc(start_time) => {
-                        max_time.timestamp() > start_time.timestamp()
+                    Some(Some(EventDate::DateTimeUtc(start_time))) => {
+                        current_time.timestamp() < start_time.timestamp()
                     }
-                },
-                _ => false,
+                    _ => false,
+                };
+                is_not_started && EventWindow::Upcoming(*current_time).contains(event, timezone)
             }
-        })
-        .collect();
-
-    event_components.sort_by_key(|event| {
-        match event.get_end().map(to_event_date) {
-            Some(Some(end_time)) => {
-                match end_time {
-                    EventDate::Date(end_date) => {
-                        let end_date_local = Utc
-                            .with_ymd_and_hms(
-                                end_date.year(),
-                                end_date.month(),
-                                end_date.day(),
-                                0,
-                                0,
-                                0,
-                            )
-                            .unwrap(); // TODO: Remove unwrap
-                        end_date_local.timestamp()
+            EventWindow::Past { since, until } => match &end_time {
+                EventDate::Date(end_date) => {
+                    since.num_days_from_ce() <= end_date.num_days_from_ce()
+                        && end_date.num_days_from_ce() < until.num_days_from_ce()
+                }
+                EventDate::DateTimeUtc(end_time) => {
+                    since.timestamp() <= end_time.timestamp()
+                        && end_time.timestamp() < until.timestamp()
+                }
+            },
+            EventWindow::Between {
+                start,
+                end,
+                inclusive,
+            } => {
+                let Some(start_time) = event_start_time(event, timezone) else {
+                    return false;
+                };
+                let include_start = matches!(inclusive, Inclusive::Start | Inclusive::Both);
+                let include_end = matches!(inclusive, Inclusive::End | Inclusive::Both);
+                let after_start = start.is_none_or(|start| {
+                    start_time > start || (include_start && start_time == start)
+                });
+                let before_end =
+                    end.is_none_or(|end| start_time < end || (include_end && start_time == end));
+                after_start && before_end
+            }
+            EventWindow::Range { first, last } => {
+                let start_time = match event.get_start().map(to_event_date) {
+                    Some(Some(start_time)) => start_time,
+                    _ => return false,
+                };
+                match (start_time, &end_time) {
+                    (EventDate::Date(start_date), EventDate::Date(end_date)) => {
+                        start_date <= *last && *end_date >= *first
                     }
-                    EventDate::DateTimeUtc(end_time) => end_time.timestamp(),
+                    (EventDate::DateTimeUtc(start_time), EventDate::DateTimeUtc(end_time)) => {
+                        start_time.with_timezone(&timezone).date_naive() <= *last
+                            && end_time.with_timezone(&timezone).date_naive() >= *first
+                    }
+                    _ => false,
                 }
             }
-            _ => unreachable!(),
         }
-    });
-
-    let events: Vec<Event> = event_components
-        .iter()
-        .flat_map(|event| {
-            // Extract required values from event
-            let (summary, start, end) = match (
-                event.get_summary().map(String::from),
-                event.get_start().and_then(to_event_date),
-                event.get_end().and_then(to_event_date),
-            ) {
-                (Some(summary), Some(start), Some(end)) => (summary, start, end),
-                // Skip event if required values are missing
-                _ => return vec![],
-            };
+    }
+}
 
-            // Extract optional values from events
-            let (description, location) = (
-                event.get_description().map(String::from),
-                event.get_location().map(String::from),
-            );
+/// Whether the current time is at or after the start but before the end, all day events in progress during the local day
+fn is_in_progress(start: &EventDate, end: &EventDate, now: DateTime<Utc>, timezone: Tz) -> bool {
+    match (start, inclusive_end(start, *end)) {
+        (EventDate::Date(start), EventDate::Date(last_day)) => {
+            let today = now.with_timezone(&timezone).date_naive();
+            *start <= today && today <= last_day
+        }
+        (EventDate::DateTimeUtc(start), EventDate::DateTimeUtc(end)) => *start <= now && now < end,
+        _ => false,
+    }
+}
 
-            let start_iso8601;
+/// Whether the event crosses a local day boundary, events ending at midnight end on the previous day
+fn is_multi_day(start: &EventDate, end: &EventDate, timezone: Tz) -> bool {
+    match (start, inclusive_end(start, *end)) {
+        (EventDate::Date(start), EventDate::Date(last_day)) => last_day > *start,
+        (EventDate::DateTimeUtc(start), EventDate::DateTimeUtc(end)) => {
+            let last_moment = end - chrono::Duration::nanoseconds(1);
+            last_moment.with_timezone(&timezone).date_naive()
+                > start.with_timezone(&timezone).date_naive()
+        }
+        _ => false,
+    }
+}
+
+/// Key by which the returned events are ordered
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    Start,
+    #[default]
+    End,
+    /// Most recently created first
+    Created,
+    /// Order of the events in the calendar, occurrences of a series following each other
+    Source,
+}
+
+/// Timestamp used for ordering events, midnight UTC for dates
+fn sort_timestamp(date: EventDate) -> i64 {
+    match date {
+        EventDate::Date(date) => date.and_time(NaiveTime::MIN).and_utc().timestamp(),
+        EventDate::DateTimeUtc(date_time) => date_time.timestamp(),
+    }
+}
+
+/// Creation timestamp of the event, from the `CREATED` property
+fn event_created(event: &icalendar::Event) -> Option<EventDate> {
+    event
+        .properties()
+        .get("CREATED")
+        .and_then(DatePerhapsTime::from_property)
+        .and_then(to_event_date)
+}
+
+fn sort_key(event: &icalendar::Event, key: SortKey) -> i64 {
+    let timestamp = match key {
+        SortKey::Start => event
+            .get_start()
+            .and_then(to_event_date)
+            .map(sort_timestamp),
+        SortKey::End => effective_end(event).map(sort_timestamp),
+        SortKey::Created => event_created(event).map(|created| -sort_timestamp(created)),
+        // Events are left in their calendar order
+        SortKey::Source => Some(0),
+    };
+    // Events missing the timestamp go last
+    timestamp.unwrap_or(i64::MAX)
+}
+
+/// Total order of events by the key, ties broken by start, summary and UID
+fn compare_events(a: &icalendar::Event, b: &icalendar::Event, key: SortKey) -> Ordering {
+    sort_key(a, key)
+        .cmp(&sort_key(b, key))
+        .then_with(|| sort_key(a, SortKey::Start).cmp(&sort_key(b, SortKey::Start)))
+        .then_with(|| a.get_summary().cmp(&b.get_summary()))
+        .then_with(|| a.get_uid().cmp(&b.get_uid()))
+}
+
+/// How much of the event descriptions is returned
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum DescriptionMode {
+    /// Truncated to `DESCRIPTION_SUMMARY_LENGTH` characters
+    #[default]
+    Summary,
+    Full,
+    None,
+}
+
+/// Truncates the text to at most the given number of characters, ending it with an ellipsis when cut
+fn truncate_text(text: &str, max_chars: usize) -> (String, bool) {
+    if text.char_indices().nth(max_chars).is_none() {
+        return (text.to_string(), false);
+    }
+    // Counting characters rather than bytes keeps multibyte characters intact
+    let kept: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    // Prefer ending at a sentence or word boundary, unless that discards most of the text
+    let sentence_end = kept
+        .match_indices(['.', '!', '?'])
+        .map(|(index, _)| index + 1)
+        .rfind(|&end| kept[end..].starts_with(char::is_whitespace))
+        .filter(|&end| end >= kept.len() / 2);
+    if let Some(end) = sentence_end {
+        return (format!("{}…", &kept[..end]), true);
+    }
+    let word_end = kept
+        .rfind(char::is_whitespace)
+        .filter(|&end| end >= kept.len() / 2)
+        .unwrap_or(kept.len());
+    (format!("{}…", kept[..word_end].trim_end()), true)
+}
+
+impl DescriptionMode {
+    /// Description in the mode, along with whether it was truncated
+    fn apply(self, description: Option<String>, summary_length: usize) -> (Option<String>, bool) {
+        match (self, description) {
+            (DescriptionMode::Summary, Some(description)) => {
+                let (description, truncated) = truncate_text(&description, summary_length);
+                (Some(description), truncated)
+            }
+            (DescriptionMode::Full, description) => (description, false),
+            _ => (None, false),
+        }
+    }
+}
+
+/// Kinds of events to return
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum EventKind {
+    Timed,
+    AllDay,
+    #[default]
+    All,
+}
+
+/// Shape of the returned list of events
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ResponseFormat {
+    /// Plain array of events
+    #[default]
+    Default,
+    /// JSON:API document with the events as resource objects
+    JsonApi,
+}
+
+/// Format of the timestamps in the `when` object
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum TimeFormat {
+    /// iso8601 dates and RFC 3339 timestamps
+    #[default]
+    Rfc3339,
+    /// Milliseconds since the Unix epoch, e.g. for charting libraries
+    EpochMs,
+}
+
+/// Selection and ordering of the returned events
+struct EventOptions {
+    window: EventWindow,
+    sort: SortKey,
+    description: DescriptionMode,
+    /// Include the legacy `date` field
+    legacy_date: bool,
+    /// Preferred language of the texts, when the calendar provides alternatives
+    language: Option<String>,
+    /// Maximum length of summarized descriptions in characters
+    description_summary_length: usize,
+    /// Summary of events without one, which are skipped when unset
+    summary_fallback: Option<String>,
+    /// Category marking events as featured
+    featured_category: String,
+    /// Return only the featured events, or only the others
+    featured: Option<bool>,
+    /// Strip venue names from map search links
+    address_cleanup: bool,
+    /// Take event links from their descriptions when missing the `URL` property
+    description_links: bool,
+    /// Colors of events without one by their first category, keyed by lowercase names
+    category_colors: HashMap<String, String>,
+    /// Map search URL with a `{query}` placeholder
+    location_url_template: String,
+    /// Names of the returned fields, all when unset
+    fields: Option<Vec<String>>,
+    /// Include the unmapped iCal properties
+    raw: bool,
+    /// Include the original `DTSTART` and `DTEND` lines
+    raw_times: bool,
+    /// List recurring events as occurrences rather than as their unexpanded series
+    expand_recurring: bool,
+    /// Collapse consecutive all day events of the same summary into one
+    merge_adjacent: bool,
+    /// Drop events with the summary, start and end of an earlier one
+    dedup: bool,
+    /// Stop converting events after this many, and one more to tell whether any were left out
+    max_events: Option<usize>,
+    /// Current time, against which events in progress are determined
+    now: DateTime<Utc>,
+    /// Name of the calendar to select events from, all when unset
+    source: Option<String>,
+    /// Clock of the times in the legacy `date` field
+    clock: ClockFormat,
+    /// Include seconds in the times of the legacy `date` field
+    seconds: bool,
+    /// Timezone of the local times and days
+    timezone: Tz,
+    /// Text the location of the returned events must contain, ignoring case
+    location: Option<String>,
+    kind: EventKind,
+    format: ResponseFormat,
+    time_format: TimeFormat,
+    /// Return only the events closest to a date, rather than all of the window
+    around: Option<Around>,
+    /// Record the durations of the stages for `/debug/timing`
+    record_timings: bool,
+}
+
+/// Date around which a number of events are returned, for previous and next navigation
+#[derive(Clone, Copy, Debug)]
+struct Around {
+    date: NaiveDate,
+    /// Number of events starting before the date
+    before: usize,
+    /// Number of events starting on or after the date
+    after: usize,
+}
+
+/// Keeps the events closest to the date of `around`, the events being ordered by their start
+fn slice_around(mut events: Vec<Event>, around: Around) -> Vec<Event> {
+    let pivot = events.partition_point(|event| event.start_day < around.date);
+    events.truncate(pivot.saturating_add(around.after));
+    events.drain(..pivot.saturating_sub(around.before));
+    events
+}
+
+impl EventOptions {
+    fn new(window: EventWindow, now: DateTime<Utc>, config: &Config) -> Self {
+        EventOptions {
+            window,
+            sort: SortKey::default(),
+            description: DescriptionMode::default(),
+            legacy_date: config.legacy_date,
+            language: None,
+            description_summary_length: config.description_summary_length,
+            summary_fallback: config.summary_fallback.clone(),
+            featured_category: config.featured_category.clone(),
+            featured: None,
+            address_cleanup: config.address_cleanup,
+            description_links: config.description_links,
+            category_colors: config.category_colors.clone(),
+            location_url_template: config.location_url_template.clone(),
+            fields: None,
+            raw: false,
+            raw_times: false,
+            expand_recurring: true,
+            merge_adjacent: false,
+            dedup: false,
+            max_events: None,
+            now,
+            source: config.default_calendar.clone(),
+            clock: config.clock_format,
+            seconds: config.time_seconds,
+            timezone: config.timezone,
+            location: None,
+            kind: EventKind::default(),
+            format: ResponseFormat::default(),
+            time_format: TimeFormat::default(),
+            around: None,
+            record_timings: false,
+        }
+    }
+}
+
+/// Recurrence rules of the event, `None` when the event doesn't recur or the rules can't be parsed
+fn event_ruleset(event: &icalendar::Event) -> Option<RRuleSet> {
+    // Construct a string containing only the recurrence rules of the event
+    let rrules = ["DTSTART", "RRULE", "EXRULE", "RDATE", "EXDATE"];
+    let mut ruleset_string = "".to_string();
+    for rrule in rrules {
+        match event.property_value(rrule) {
+            Some(rule) => ruleset_string.push_str(&format!("{rrule}:{rule}\n")),
+            None => {
+                let multi_rules = event.multi_properties().get(rrule);
+                if let Some(props) = multi_rules {
+                    for prop in props {
+                        ruleset_string.push_str(&format!("{rrule}:{}\n", prop.value()))
+                    }
+                }
+            }
+        }
+    }
+
+    // Parse recurrence rules
+    ruleset_string.parse().ok()
+}
+
+/// Human readable description of an `RRULE`, e.g. `every 2 weeks on Tuesday and Thursday`
+fn describe_rrule(rrule: &str) -> String {
+    let parts: BTreeMap<&str, &str> = rrule
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .collect();
+    let unit = match parts.get("FREQ").copied() {
+        Some("SECONDLY") => "second",
+        Some("MINUTELY") => "minute",
+        Some("HOURLY") => "hour",
+        Some("DAILY") => "day",
+        Some("WEEKLY") => "week",
+        Some("MONTHLY") => "month",
+        Some("YEARLY") => "year",
+        _ => return "irregularly".to_string(),
+    };
+    let interval: u32 = parts
+        .get("INTERVAL")
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(1);
+    let days: Vec<String> = parts
+        .get("BYDAY")
+        .map(|days| days.split(',').filter_map(describe_weekday).collect())
+        .unwrap_or_default();
+    let days = match &days[..] {
+        [] => None,
+        [day] => Some(day.clone()),
+        [rest @ .., last] => Some(format!("{} and {last}", rest.join(", "))),
+    };
+    match (interval, days) {
+        (1, Some(days)) if unit == "week" => format!("every {days}"),
+        (1, Some(days)) => format!("every {unit} on {days}"),
+        (1, None) => format!("every {unit}"),
+        (interval, Some(days)) => format!("every {interval} {unit}s on {days}"),
+        (interval, None) => format!("every {interval} {unit}s"),
+    }
+}
+
+/// Describes a `BYDAY` value, e.g. `TU` as `Tuesday` and `-1FR` as `the last Friday`
+fn describe_weekday(day: &str) -> Option<String> {
+    let split = day.len().checked_sub(2)?;
+    let (ordinal, weekday) = day.split_at_checked(split)?;
+    let weekday = match weekday {
+        "MO" => "Monday",
+        "TU" => "Tuesday",
+        "WE" => "Wednesday",
+        "TH" => "Thursday",
+        "FR" => "Friday",
+        "SA" => "Saturday",
+        "SU" => "Sunday",
+        _ => return None,
+    };
+    let ordinal = match ordinal.trim_start_matches('+') {
+        "" => return Some(weekday.to_string()),
+        "1" => "first",
+        "2" => "second",
+        "3" => "third",
+        "4" => "fourth",
+        "-1" => "last",
+        "-2" => "second to last",
+        ordinal => return Some(format!("the {ordinal}. {weekday}")),
+    };
+    Some(format!("the {ordinal} {weekday}"))
+}
+
+/// Recurring series with its next occurrence
+#[derive(Serialize, Debug)]
+struct Series {
+    uid: Option<String>,
+    summary: String,
+    /// iso8601 formatted start of the next occurrence
+    next_occurrence: String,
+    /// Human readable description of the recurrence
+    recurrence: String,
+}
+
+/// Calendar level metadata
+#[derive(Serialize, Debug)]
+struct CalendarInfo {
+    name: Option<String>,
+    description: Option<String>,
+    timezone: Option<String>,
+    /// Number of events in the calendar, recurring series counted once
+    event_count: usize,
+}
+
+fn calendar_info(calendar: &Calendar) -> CalendarInfo {
+    CalendarInfo {
+        name: calendar.get_name().map(String::from),
+        description: calendar.get_description().map(String::from),
+        timezone: calendar.get_timezone().map(String::from),
+        event_count: calendar.events().count(),
+    }
+}
+
+/// Next occurrence of each recurring series, soonest first
+fn data_to_series(calendar: &Calendar, now: DateTime<Utc>) -> Vec<Series> {
+    let mut series: Vec<(i64, Series)> = calendar
+        .iter()
+        .filter_map(|component| match component {
+            CalendarComponent::Event(event) => Some(event),
+            _ => None,
+        })
+        // Overridden occurrences share the UID of their series
+        .filter(|event| event.get_recurrence_id().is_none())
+        .filter_map(|event| {
+            let rrule = event.property_value("RRULE")?;
+            let summary = event.get_summary().map(normalize_whitespace)?;
+            let next = event_ruleset(event)?
+                .after(now.with_timezone(&rrule::Tz::UTC))
+                .all(1)
+                .dates
+                .into_iter()
+                .next()?;
+            let next_occurrence = match event.get_start().and_then(to_event_date)? {
+                EventDate::Date(_) => to_iso8601(&EventDate::Date(next.date_naive())),
+                EventDate::DateTimeUtc(_) => to_iso8601(&EventDate::DateTimeUtc(next.to_utc())),
+            };
+            Some((
+                next.timestamp(),
+                Series {
+                    uid: event.get_uid().map(String::from),
+                    summary,
+                    next_occurrence,
+                    recurrence: describe_rrule(rrule),
+                },
+            ))
+        })
+        .collect();
+    series.sort_by_key(|(timestamp, _)| *timestamp);
+    series.into_iter().map(|(_, series)| series).collect()
+}
+
+/// Occurrences replaced by overriding components, as their UIDs and original start timestamps.
+/// The overrides may move the occurrences to dates the recurrence rules don't generate.
+fn overridden_occurrences(calendar: &Calendar) -> HashSet<(String, i64)> {
+    calendar
+        .events()
+        .filter(|event| event.property_value("RRULE").is_none())
+        .filter_map(|event| {
+            let uid = event.get_uid()?;
+            let recurrence_id = event.get_recurrence_id().and_then(to_event_date)?;
+            Some((uid.to_string(), sort_timestamp(recurrence_id)))
+        })
+        .collect()
+}
+
+fn data_to_events(
+    calendar: &Calendar,
+    spaces: &[Space],
+    options: &EventOptions,
+) -> Result<Vec<Event>, warp::Rejection> {
+    timed_data_to_events(calendar, spaces, options).map(|(events, _)| events)
+}
+
+/// Events of the calendar along with the durations of the stages of selecting them
+fn timed_data_to_events(
+    calendar: &Calendar,
+    spaces: &[Space],
+    options: &EventOptions,
+) -> Result<(Vec<Event>, StageTimings), warp::Rejection> {
+    let started = Instant::now();
+    let window = &options.window;
+    let language = options.language.as_deref();
+    let overridden = overridden_occurrences(calendar);
+    let mut event_components: Vec<icalendar::Event> = calendar
+        .iter()
+        // Filter out components other than of type event
+        .flat_map(|component| match component {
+            CalendarComponent::Event(event) => vec![event],
+            _ => vec![],
+        })
+        // Populate recurring events
+        .flat_map(|event| {
+            let rrule = match event_ruleset(event) {
+                // Append only the original event if parsing recurrence fails or recurrence rules don't exist
+                None if window.contains(event, options.timezone) => {
+                    return vec![event.to_owned()];
+                }
+                None => return vec![],
+                Some(rrule) => rrule,
+            };
+
+            // Make clones of the original event with new start and end timestamps
+            const MAX_RECURRENCES: u16 = 100;
+            let dates = rrule.all(MAX_RECURRENCES).dates;
+            let occurrences = dates
+                .iter()
+                .flat_map(|date| {
+                    // Overriding components take the place of the generated occurrence
+                    let occurrence_start = match event.get_start().and_then(to_event_date) {
+                        Some(EventDate::Date(_)) => {
+                            sort_timestamp(EventDate::Date(date.date_naive()))
+                        }
+                        _ => date.timestamp(),
+                    };
+                    if let Some(uid) = event.get_uid()
+                        && overridden.contains(&(uid.to_string(), occurrence_start))
+                    {
+                        return vec![];
+                    }
+                    let mut event_clone = event.clone();
+                    match (
+                        // TODO: Invoking to_event_date can be omitted, remove it
+                        event.get_start().map(to_event_date),
+                        event_end(event).map(to_event_date),
+                    ) {
+                        // Timestamps without time
+                        (
+                            Some(Some(EventDate::Date(original_start_date))),
+                            Some(Some(EventDate::Date(original_end_date))),
+                        ) => {
+                            let duration = Days::new(
+                                (original_end_date.num_days_from_ce()
+                                    - original_start_date.num_days_from_ce())
+                                    as u64,
+                            );
+                            let event_end = date.to_owned() + duration;
+                            let event_start = DatePerhapsTime::Date(date.date_naive());
+                            event_clone.append_property(event_start.to_property("RECURRENCE-ID"));
+                            event_clone.starts(event_start);
+                            event_clone.ends(DatePerhapsTime::Date(event_end.date_naive()));
+                            vec![event_clone]
+                        }
+                        // Timestamps with time
+                        (
+                            Some(Some(EventDate::DateTimeUtc(original_start_date))),
+                            Some(Some(EventDate::DateTimeUtc(original_end_date))),
+                        ) => {
+                            let duration =
+                                original_end_date.signed_duration_since(original_start_date);
+                            let event_end_utc = (*date + duration).to_utc();
+                            let event_start = DatePerhapsTime::DateTime(date.to_utc().into());
+                            event_clone.append_property(event_start.to_property("RECURRENCE-ID"));
+                            event_clone.starts(event_start);
+                            event_clone.ends(DatePerhapsTime::DateTime(event_end_utc.into()));
+                            vec![event_clone]
+                        }
+                        _ => {
+                            // Skip if event start and end are expressed in differing formats, or when parsing fails
+                            println!("warning: skipping event {:?} recurrence", event);
+                            vec![]
+                        }
+                    }
+                })
+                .filter(|occurrence| window.contains(occurrence, options.timezone));
+            // Unexpanded series are listed when any of their occurrences are
+            match options.expand_recurring {
+                true => occurrences.collect(),
+                false if occurrences.count() > 0 => vec![event.to_owned()],
+                false => vec![],
+            }
+        })
+        .filter(|event| match &options.source {
+            Some(source) => event.property_value(CALENDAR_NAME_PROPERTY) == Some(source.as_str()),
+            None => true,
+        })
+        .collect();
+
+    if options.merge_adjacent {
+        event_components = merge_adjacent_all_day(event_components);
+    }
+    let filtered = Instant::now();
+
+    if !matches!(options.sort, SortKey::Source) {
+        event_components.sort_by(|a, b| compare_events(a, b, options.sort));
+    }
+    // Show the most recently ended events first in the archive
+    if let (EventWindow::Past { .. }, SortKey::Start | SortKey::End) = (window, options.sort) {
+        event_components.reverse();
+    }
+    let sorted = Instant::now();
+
+    // Slicing around a date needs the events on both sides of it
+    let max_events = match options.around {
+        Some(_) => None,
+        None => options.max_events,
+    };
+    // Unlike `DEDUP_KEY`, this catches duplicates with different UIDs
+    let mut seen = HashSet::new();
+    let events: Vec<Event> = event_components
+        .iter()
+        .flat_map(|event| {
+            // Extract required values from event
+            let (summary, start, end) = match (
+                localized_value(event, "SUMMARY", language)
+                    .map(normalize_whitespace)
+                    .or_else(|| options.summary_fallback.clone()),
+                event.get_start().and_then(to_event_date),
+                event_end(event).and_then(to_event_date),
+            ) {
+                (Some(summary), Some(start), Some(end)) => (summary, start, end),
+                // Skip event if required values are missing
+                _ => return vec![],
+            };
+
+            // Extract optional values from events
+            let ((description, description_truncated), location, color) = (
+                options.description.apply(
+                    localized_value(event, "DESCRIPTION", language)
+                        .map(normalize_multiline)
+                        .filter(|description| !description.is_empty()),
+                    options.description_summary_length,
+                ),
+                localized_value(event, "LOCATION", language)
+                    .map(normalize_whitespace)
+                    .filter(|location| !location.is_empty()),
+                event_color(event).or_else(|| {
+                    event_categories(event)
+                        .first()
+                        .map(|category| category_color(category, &options.category_colors))
+                }),
+            );
+
+            let start_day = match &start {
+                EventDate::Date(start) => *start,
+                EventDate::DateTimeUtc(start) => {
+                    start.with_timezone(&options.timezone).date_naive()
+                }
+            };
+
+            let last_day = match inclusive_end(&start, end) {
+                EventDate::Date(last_day) => last_day,
+                EventDate::DateTimeUtc(end) => (end - chrono::Duration::nanoseconds(1))
+                    .with_timezone(&options.timezone)
+                    .date_naive(),
+            }
+            .max(start_day);
+
+            let in_progress = is_in_progress(&start, &end, options.now, options.timezone);
+            let multi_day = is_multi_day(&start, &end, options.timezone);
+            let duration_minutes = match (&start, &end) {
+                (EventDate::Date(start), EventDate::Date(end)) => {
+                    end.signed_duration_since(*start).num_minutes()
+                }
+                (EventDate::DateTimeUtc(start), EventDate::DateTimeUtc(end)) => {
+                    end.signed_duration_since(*start).num_minutes()
+                }
+                _ => 0,
+            };
+            let today = options.now.with_timezone(&options.timezone).date_naive();
+            let days_until = start_day.signed_duration_since(today).num_days();
+
+            let timestamp = |date: &EventDate, formatted: String| match options.time_format {
+                TimeFormat::Rfc3339 => Timestamp::Formatted(formatted),
+                TimeFormat::EpochMs => Timestamp::EpochMillis(to_epoch_millis(date)),
+            };
+            let start_iso8601;
             let end_iso8601;
+            let when;
             let date_string = match (&start, end) {
                 (EventDate::Date(start), EventDate::Date(end)) => {
-                    start_iso8601 = format!("{}", start.format("%Y-%m-%d"));
-                    end_iso8601 = format!("{}", end.format("%Y-%m-%d"));
-                    if end.signed_duration_since(*start).num_days() == 1 {
-                        format!("{}", start.format("%d/%m/%Y"))
-                    } else {
-                        format!("{} - {}", start.format("%d/%m/%Y"), end.format("%d/%m/%Y"))
+                    start_iso8601 = to_iso8601(&EventDate::Date(*start));
+                    end_iso8601 = to_iso8601(&EventDate::Date(end));
+                    when = When {
+                        start: timestamp(&EventDate::Date(*start), start_iso8601.clone()),
+                        end: timestamp(&EventDate::Date(end), end_iso8601.clone()),
+                        all_day: true,
+                        timezone: None,
+                    };
+                    match inclusive_end(&EventDate::Date(*start), EventDate::Date(end)) {
+                        EventDate::Date(last_day) if last_day > *start => format!(
+                            "{} - {}",
+                            start.format("%d/%m/%Y"),
+                            last_day.format("%d/%m/%Y")
+                        ),
+                        _ => format!("{}", start.format("%d/%m/%Y")),
                     }
                 }
                 (EventDate::DateTimeUtc(start), EventDate::DateTimeUtc(end)) => {
-                    start_iso8601 = start.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true);
-                    end_iso8601 = end.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true);
-                    let local_start = start.with_timezone(&Local);
-                    let local_end = end.with_timezone(&Local);
-                    if local_end.signed_duration_since(local_start).num_days() < 1 {
+                    start_iso8601 = to_iso8601(&EventDate::DateTimeUtc(*start));
+                    end_iso8601 = to_iso8601(&EventDate::DateTimeUtc(end));
+                    let local_start = start.with_timezone(&options.timezone);
+                    let local_end = end.with_timezone(&options.timezone);
+                    when = When {
+                        start: timestamp(
+                            &EventDate::DateTimeUtc(*start),
+                            local_start.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, false),
+                        ),
+                        end: timestamp(
+                            &EventDate::DateTimeUtc(end),
+                            local_end.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, false),
+                        ),
+                        all_day: false,
+                        timezone: Some(options.timezone.name().to_string()),
+                    };
+                    let time_format = options.clock.time_format(options.seconds);
+                    if local_end == local_start {
+                        // Points in time have no range to show
+                        format!(
+                            "{} {}",
+                            local_start.format("%d/%m/%Y"),
+                            local_start.format(time_format)
+                        )
+                    } else if local_end.signed_duration_since(local_start).num_days() < 1 {
                         format!(
                             "{} {} - {}",
                             local_start.format("%d/%m/%Y"),
-                            local_start.format("%H:%M"),
-                            local_end.format("%H:%M")
+                            local_start.format(time_format),
+                            local_end.format(time_format)
                         )
                     } else {
                         format!(
-                            "{} - {}",
-                            local_start.format("%d/%m/%Y %H:%M"),
-                            local_end.format("%d/%m %H:%M")
+                            "{} {} - {} {}",
+                            local_start.format("%d/%m/%Y"),
+                            local_start.format(time_format),
+                            local_end.format("%d/%m"),
+                            local_end.format(time_format)
                         )
                     }
                 }
@@ -335,128 +1733,3159 @@ fn data_to_events(
                 _ => return vec![],
             };
 
+            // Occurrences of expanded and overridden series carry a recurrence id
+            let recurrence_id = event
+                .get_recurrence_id()
+                .and_then(to_event_date)
+                .map(|date| to_iso8601(&date));
+            let recurring = recurrence_id.is_some() || event.property_value("RRULE").is_some();
+
+            let id = event_id(
+                event.get_uid(),
+                recurrence_id.as_deref(),
+                &summary,
+                &start_iso8601,
+                &end_iso8601,
+            );
+
             let location_with_link = location.map(|location| Location {
-                url: url_for_location(&location, &spaces),
+                url: url_for_location(
+                    &location,
+                    spaces,
+                    options.address_cleanup,
+                    &options.location_url_template,
+                ),
                 string: location,
             });
 
             vec![Event {
+                id,
                 summary,
                 description,
-                date: date_string,
+                description_truncated,
+                date: options.legacy_date.then_some(date_string),
+                when,
                 start_iso8601,
                 end_iso8601,
                 location: location_with_link,
+                color,
+                in_progress,
+                multi_day,
+                duration_minutes,
+                days_until,
+                recurring,
+                recurrence_id,
+                rrule: (!options.expand_recurring)
+                    .then(|| event.property_value("RRULE").map(String::from))
+                    .flatten(),
+                created: event_created(event).map(|created| to_iso8601(&created)),
+                calendar_label: event
+                    .property_value(CALENDAR_LABEL_PROPERTY)
+                    .map(String::from),
+                calendar_color: event
+                    .property_value(CALENDAR_COLOR_PROPERTY)
+                    .map(String::from),
+                raw_properties: options.raw.then(|| raw_properties(event)),
+                dtstart_raw: options
+                    .raw_times
+                    .then(|| event.property_value(&raw_time_property("DTSTART")))
+                    .flatten()
+                    .map(String::from),
+                dtend_raw: options
+                    .raw_times
+                    .then(|| event.property_value(&raw_time_property("DTEND")))
+                    .flatten()
+                    .map(String::from),
+                attachments: event_attachments(event),
+                link: event.property_value("URL").map(String::from).or_else(|| {
+                    options
+                        .description_links
+                        .then(|| localized_value(event, "DESCRIPTION", language))
+                        .flatten()
+                        .and_then(first_link)
+                }),
+                start_day,
+                last_day,
+                url: event.property_value("URL").map(String::from),
+                featured: is_featured(event, &options.featured_category),
+                categories: event_categories(event),
             }]
         })
+        .filter(|event| match options.kind {
+            EventKind::All => true,
+            EventKind::Timed => !event.when.all_day,
+            EventKind::AllDay => event.when.all_day,
+        })
+        .filter(|event| {
+            options
+                .featured
+                .is_none_or(|featured| event.featured == featured)
+        })
+        .filter(|event| match &options.location {
+            Some(location) => event.location.as_ref().is_some_and(|event_location| {
+                contains_ignore_case(&event_location.string, location)
+            }),
+            None => true,
+        })
+        .filter(|event| {
+            !options.dedup
+                || seen.insert((
+                    event.summary.clone(),
+                    event.start_iso8601.clone(),
+                    event.end_iso8601.clone(),
+                ))
+        })
+        .take(max_events.map_or(usize::MAX, |max_events| max_events.saturating_add(1)))
         .collect();
 
-    Ok(events)
-}
+    let timings = StageTimings {
+        filter_ms: millis(filtered - started),
+        sort_ms: millis(sorted - filtered),
+        convert_ms: millis(sorted.elapsed()),
+        ..Default::default()
+    };
 
-#[cached(
-    time = 600,
-    time_refresh = true,
-    sync_writes = "default",
-    result = true
-)]
-async fn get_events() -> Result<Vec<Event>, warp::Rejection> {
-    let spaces_data = fetch_spaces().await.unwrap_or_default();
-    let spaces = parse_spaces(spaces_data).unwrap_or_default();
-    let calendar_data = fetch_calendar("https://calendar.google.com/calendar/ical/c_g2eqt2a7u1fc1pahe2o0ecm7as%40group.calendar.google.com/public/basic.ics").await.unwrap_or_default();
-    let calendar = match process_calendar(calendar_data) {
-        Ok(calendar) => calendar,
-        Err(err) => {
-            return Err(reject::custom(Error {
-                message: "The remote calendar could not be processed.".to_string(),
-                details: Some(format! {"{:?}", err}),
-            }));
-        }
+    let events = match options.around {
+        Some(around) => slice_around(events, around),
+        None => events,
     };
-    let now = Utc::now();
-    data_to_events(calendar, spaces, now)
+    Ok((events, timings))
 }
 
-async fn events() -> Result<impl Reply, warp::Rejection> {
-    let events = get_events().await?;
-    let json = warp::reply::json(&events);
-    Ok(warp::reply::with_status(json, StatusCode::OK))
+/// Durations of the stages of the latest `/events` request in milliseconds
+#[derive(Serialize, Clone, Debug, Default)]
+struct StageTimings {
+    /// Getting the calendar from the cache, or from the upstream when the cache has expired
+    fetch_ms: f64,
+    /// Parsing the calendars at their latest refresh, which needn't be during the request
+    parse_ms: Option<f64>,
+    /// Expanding recurring events and selecting the requested ones
+    filter_ms: f64,
+    sort_ms: f64,
+    /// Converting the selected events into their API representation
+    convert_ms: f64,
+    serialize_ms: f64,
 }
 
-pub fn filter() -> BoxedFilter<(impl Reply,)> {
-    warp::path("events").and_then(events).boxed()
+/// Stages of the latest `/events` request, recorded in `DEBUG_MODE`
+static LAST_TIMINGS: Mutex<Option<StageTimings>> = Mutex::new(None);
+
+/// Duration of parsing the calendars at their latest refresh
+static LAST_PARSE_DURATION: Mutex<Option<Duration>> = Mutex::new(None);
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
 }
 
-#[cfg(test)]
-mod tests {
-    use std::assert_matches;
+/// Whether the text contains the pattern, ignoring case
+fn contains_ignore_case(text: &str, pattern: &str) -> bool {
+    text.to_lowercase().contains(&pattern.to_lowercase())
+}
 
-    use super::*;
+/// Calendar and university spaces, which are cached between requests
+struct CalendarData {
+    calendar: Arc<Calendar>,
+    spaces: Vec<Space>,
+    /// When the calendar was fetched from the upstream
+    fetched: DateTime<Utc>,
+    /// When the upstream last confirmed the calendar, by sending it or reporting it unchanged
+    checked: DateTime<Utc>,
+}
 
-    fn now() -> DateTime<Utc> {
-        Utc.with_ymd_and_hms(2026, 2, 2, 16, 32, 11).unwrap()
+/// How long fetched calendars are cached, set by `init` before the cache is created
+static CACHE_TTL: OnceLock<Duration> = OnceLock::new();
+
+/// Most recently fetched calendar, served when refreshing the cache fails
+static LAST_GOOD_CALENDAR_DATA: Mutex<Option<Arc<CalendarData>>> = Mutex::new(None);
+
+/// Set once the calendar has been fetched successfully, reported by `/health/ready`
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Seconds after which clients should retry when no calendar is available
+const RETRY_AFTER_SECS: u64 = 30;
+
+/// Upcoming events as JSON, sent to `/events/stream` clients whenever they change
+static EVENT_UPDATES: LazyLock<broadcast::Sender<String>> =
+    LazyLock::new(|| broadcast::channel(16).0);
+
+/// Most recently broadcast events, against which refreshed events are compared
+static LAST_BROADCAST: Mutex<Option<String>> = Mutex::new(None);
+
+fn upcoming_events_json(
+    data: &CalendarData,
+    config: &Config,
+    now: DateTime<Utc>,
+) -> Result<String, warp::Rejection> {
+    let mut options = EventOptions::new(EventWindow::Upcoming(now), now, config);
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    cap_events(&mut events, config.max_events);
+    serde_json::to_string(&events).map_err(|err| {
+        reject::custom(Error {
+            message: "The events could not be serialized.".to_string(),
+            details: Some(err.to_string()),
+            ..Default::default()
+        })
+    })
+}
+
+fn broadcast_if_changed(data: &CalendarData, config: &Config) {
+    let Ok(payload) = upcoming_events_json(data, config, Utc::now()) else {
+        return;
+    };
+    let Ok(mut last_broadcast) = LAST_BROADCAST.lock() else {
+        return;
+    };
+    if last_broadcast.as_ref() != Some(&payload) {
+        // Sending fails only when no client is listening
+        let _ = EVENT_UPDATES.send(payload.clone());
+        *last_broadcast = Some(payload);
     }
+}
 
-    #[test]
-    fn test_event_parsing() {
-        let calendar_data: &'static str = include_str!("test-data/basic.ics");
-        let now = now();
-        let calendar = Calendar::from_str(calendar_data).unwrap();
-        let result = data_to_events(calendar, vec![], now).unwrap();
-        assert_matches!(&result[..], [Event {
-            summary, description: Some(description),
-            date: _,
-            location: Some(Location{string: location_string, url: _}),
-            start_iso8601: _,
-            end_iso8601: _,
-        }] if summary == "Test Event"
-            && description == "Test description"
-            && location_string == "Test Location");
+/// Failure of fetching or processing the calendars
+#[derive(Serialize, Clone, Debug)]
+struct FetchError {
+    /// RFC 3339 timestamp of the failure
+    at: String,
+    message: String,
+}
+
+/// Most recent failure, reported by `/health`
+static LAST_FETCH_ERROR: Mutex<Option<FetchError>> = Mutex::new(None);
+
+/// Characters of the failure kept for `/health`, which is public unless feeds are signed
+const MAX_FETCH_ERROR_CHARS: usize = 500;
+
+fn remember_fetch_error(message: String) {
+    if let Ok(mut last_error) = LAST_FETCH_ERROR.lock() {
+        *last_error = Some(FetchError {
+            at: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            message: truncate_text(&message, MAX_FETCH_ERROR_CHARS).0,
+        });
     }
+}
 
-    #[test]
-    fn test_recurrence_parsing() {
-        let calendar_data: &'static str = include_str!("test-data/recurrence.ics");
-        let now = now();
-        let calendar = Calendar::from_str(calendar_data).unwrap();
-        let result = data_to_events(calendar, vec![], now).unwrap();
+fn last_good_calendar_data() -> Option<Arc<CalendarData>> {
+    LAST_GOOD_CALENDAR_DATA
+        .lock()
+        .ok()
+        .and_then(|last_good| last_good.clone())
+}
+
+fn remember_calendar_data(data: &Arc<CalendarData>) {
+    if let Ok(mut last_good) = LAST_GOOD_CALENDAR_DATA.lock() {
+        *last_good = Some(data.clone());
+    }
+    READY.store(true, atomic::Ordering::Relaxed);
+}
+
+/// Fetches the calendar into the cache ahead of requests, so that they seldom wait for the upstream
+pub async fn refresh_calendar_data(config: &Config) {
+    match get_cached_calendar_data_prime_cache(config).await {
+        Ok(data) => {
+            remember_calendar_data(&data);
+            broadcast_if_changed(&data, config);
+        }
+        Err(err) => {
+            let message = err.details.unwrap_or(err.message);
+            eprintln!("background refresh failed: {message}");
+            remember_fetch_error(message);
+        }
+    }
+}
+
+/// Whether a calendar last refreshed at `checked` is recent enough to be served when refreshing fails
+fn is_servable_stale(
+    checked: DateTime<Utc>,
+    now: DateTime<Utc>,
+    max_age: Option<Duration>,
+) -> bool {
+    max_age.is_none_or(|max_age| (now - checked).to_std().unwrap_or_default() <= max_age)
+}
+
+async fn get_calendar_data(config: &Config) -> Result<Arc<CalendarData>, warp::Rejection> {
+    match get_cached_calendar_data(config).await {
+        Ok(data) => {
+            remember_calendar_data(&data);
+            Ok(data)
+        }
+        Err(err) => {
+            remember_fetch_error(err.details.clone().unwrap_or(err.message.clone()));
+            match last_good_calendar_data() {
+                Some(data)
+                    if is_servable_stale(data.checked, Utc::now(), config.cache_stale_max) =>
+                {
+                    eprintln!(
+                        "WARNING: serving stale calendar last refreshed at {}, beyond the cache TTL of {}s: {}",
+                        data.checked,
+                        config.cache_ttl.as_secs(),
+                        err.details.unwrap_or(err.message)
+                    );
+                    Ok(data)
+                }
+                _ => Err(reject::custom(Error {
+                    message: "503 - The calendar is temporarily unavailable".to_string(),
+                    details: err.details,
+                    status: Some(StatusCode::SERVICE_UNAVAILABLE),
+                    retry_after: Some(RETRY_AFTER_SECS),
+                    ..Default::default()
+                })),
+            }
+        }
+    }
+}
+
+#[cached(
+    ty = "cached::TimedCache<(), Arc<CalendarData>>",
+    create = "{ cached::TimedCache::with_lifespan_and_refresh(*CACHE_TTL.get().expect(\"events::init not called\"), true) }",
+    key = "()",
+    convert = "{}",
+    sync_writes = "default",
+    result = true
+)]
+async fn get_cached_calendar_data(config: &Config) -> Result<Arc<CalendarData>, Error> {
+    let mut errors = vec![];
+    let mut fetched = vec![];
+    let mut changed = false;
+    for source in &config.calendars {
+        match fetch_calendar(&source.url).await {
+            Ok((calendar_data, modified)) => {
+                changed |= modified;
+                fetched.push((source, calendar_data));
+            }
+            Err(err) => errors.push(format!("{}: {err:#}", redact_url(&source.url))),
+        }
+    }
+    // Skip processing the calendars again when none of them changed upstream
+    if !changed
+        && errors.is_empty()
+        && let Some(data) = last_good_calendar_data()
+    {
+        return Ok(Arc::new(CalendarData {
+            calendar: data.calendar.clone(),
+            spaces: data.spaces.clone(),
+            fetched: data.fetched,
+            checked: Utc::now(),
+        }));
+    }
+
+    let spaces_data = fetch_spaces().await.unwrap_or_default();
+    let spaces = parse_spaces(spaces_data).unwrap_or_default();
+    let mut calendar = Calendar::new();
+    let mut seen = HashSet::new();
+    let parse_started = Instant::now();
+    for (source, calendar_data) in fetched {
+        match process_calendar(calendar_data) {
+            Ok(mut source_calendar) => {
+                remove_hidden_events(&mut source_calendar, &config.hidden_uids);
+                merge_calendar(
+                    &mut calendar,
+                    source_calendar,
+                    source,
+                    &mut seen,
+                    config.dedup_key,
+                )
+            }
+            Err(err) => errors.push(format!("{}: {err:#}", redact_url(&source.url))),
+        }
+    }
+    if let Ok(mut parse_duration) = LAST_PARSE_DURATION.lock() {
+        *parse_duration = Some(parse_started.elapsed());
+    }
+    // Serve the calendars that could be processed, unless none could
+    if errors.len() == config.calendars.len() {
+        return Err(Error {
+            message: "The remote calendar could not be processed.".to_string(),
+            details: Some(errors.join("\n")),
+            ..Default::default()
+        });
+    }
+    for error in errors {
+        eprintln!("skipping calendar {error}");
+        remember_fetch_error(error);
+    }
+    let fetched = Utc::now();
+    Ok(Arc::new(CalendarData {
+        calendar: Arc::new(calendar),
+        spaces,
+        fetched,
+        checked: fetched,
+    }))
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    /// First day of the requested range, as YYYY-MM-DD
+    from: Option<String>,
+    /// Last day of the requested range, as YYYY-MM-DD
+    to: Option<String>,
+    /// Exclude events which are already in progress
+    upcoming_only: Option<bool>,
+    sort: Option<SortKey>,
+    description: Option<DescriptionMode>,
+    /// Include the legacy `date` field, overriding `LEGACY_DATE_FIELD`
+    legacy_date: Option<bool>,
+    /// Preferred language of the texts, e.g. `fi`
+    lang: Option<String>,
+    /// Comma separated names of the returned fields
+    fields: Option<String>,
+    /// Include the unmapped iCal properties
+    raw: Option<bool>,
+    /// Include the original `DTSTART` and `DTEND` lines
+    raw_times: Option<bool>,
+    /// `false` to list recurring events as their unexpanded series
+    expand_recurring: Option<bool>,
+    /// Collapse consecutive all day events of the same summary into one
+    merge_adjacent: Option<bool>,
+    /// Drop events with the summary, start and end of an earlier one
+    dedup: Option<bool>,
+    /// `true` for only the featured events, `false` for only the others
+    featured: Option<bool>,
+    /// Name of the configured calendar to select events from
+    source: Option<String>,
+    /// `12` or `24` hour clock, overriding `CLOCK_FORMAT`
+    clock: Option<ClockFormat>,
+    /// Include seconds in the times, overriding `TIME_SECONDS`
+    seconds: Option<bool>,
+    /// Text the location of the returned events must contain, ignoring case
+    location: Option<String>,
+    /// `timed`, `allday` or `all` events
+    #[serde(rename = "type")]
+    kind: Option<EventKind>,
+    /// `jsonapi` for a JSON:API document instead of a plain array
+    format: Option<ResponseFormat>,
+    /// `epoch_ms` for the `when` timestamps as milliseconds since the Unix epoch
+    time_format: Option<TimeFormat>,
+    /// Date around which to return events, as YYYY-MM-DD
+    around: Option<String>,
+    /// Number of events before `around`, 5 by default
+    before: Option<usize>,
+    /// Number of events on or after `around`, 5 by default
+    after: Option<usize>,
+}
+
+/// Years of the dates accepted in requests, others being more likely typos than intended
+const PLAUSIBLE_YEARS: std::ops::RangeInclusive<i32> = 1900..=2999;
+
+fn parse_date_param(name: &str, value: &str) -> Result<NaiveDate, warp::Rejection> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        reject::custom(Error::invalid_param(
+            name,
+            &format!("Parameter {name} must be a date in YYYY-MM-DD format."),
+        ))
+    })?;
+    if !PLAUSIBLE_YEARS.contains(&date.year()) {
+        return Err(reject::custom(Error::invalid_param(
+            name,
+            &format!(
+                "Parameter {name} must be a date between the years {} and {}.",
+                PLAUSIBLE_YEARS.start(),
+                PLAUSIBLE_YEARS.end()
+            ),
+        )));
+    }
+    Ok(date)
+}
+
+/// Range from the given dates, defaulting to a year from today
+fn range_window(
+    from: Option<&str>,
+    to: Option<&str>,
+    now: DateTime<Utc>,
+    timezone: Tz,
+) -> Result<EventWindow, warp::Rejection> {
+    let first = match from {
+        Some(from) => parse_date_param("from", from)?,
+        None => now.with_timezone(&timezone).date_naive(),
+    };
+    let last = match to {
+        Some(to) => parse_date_param("to", to)?,
+        None => first + Days::new(365),
+    };
+    if first > last {
+        let error = match from {
+            Some(_) => Error::invalid_param("from", "Parameter from must not be after to."),
+            None => Error::invalid_param("to", "Parameter to must not be before today."),
+        };
+        return Err(reject::custom(error));
+    }
+    Ok(EventWindow::Range { first, last })
+}
+
+/// Formats the timestamp as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+fn to_http_date(date_time: &DateTime<Utc>) -> String {
+    date_time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the client's `If-Modified-Since` timestamp is at or after the calendar fetch
+fn is_not_modified(if_modified_since: Option<&str>, fetched: &DateTime<Utc>) -> bool {
+    // HTTP dates are compatible with RFC 2822, but only have second precision
+    match if_modified_since.map(DateTime::parse_from_rfc2822) {
+        Some(Ok(since)) => since.timestamp() >= fetched.timestamp(),
+        _ => false,
+    }
+}
+
+/// Serializes the events with only the given top level fields, ignoring unknown names
+fn select_fields(events: &[Event], fields: &[String]) -> Vec<serde_json::Value> {
+    events
+        .iter()
+        .map(|event| match serde_json::to_value(event) {
+            Ok(serde_json::Value::Object(mut object)) => {
+                object.retain(|key, _| fields.contains(key));
+                serde_json::Value::Object(object)
+            }
+            _ => serde_json::Value::Null,
+        })
+        .collect()
+}
+
+/// Wraps the serialized events into a JSON:API document, moving their `id` out of the attributes
+fn to_json_api(events: Vec<serde_json::Value>) -> serde_json::Value {
+    let data: Vec<serde_json::Value> = events
+        .into_iter()
+        .map(|mut event| {
+            let id = event
+                .as_object_mut()
+                .and_then(|object| object.remove("id"))
+                .unwrap_or_default();
+            serde_json::json!({"type": "event", "id": id, "attributes": event})
+        })
+        .collect();
+    serde_json::json!({ "data": data })
+}
+
+/// Caps the events to `max_events`, returning whether any were left out
+fn cap_events(events: &mut Vec<Event>, max_events: usize) -> bool {
+    let truncated = events.len() > max_events;
+    events.truncate(max_events);
+    truncated
+}
+
+/// Signals whether events were left out due to `MAX_EVENTS`
+fn with_truncated_header(reply: impl Reply, truncated: bool) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    if truncated {
+        response
+            .headers_mut()
+            .insert("X-Truncated", warp::http::HeaderValue::from_static("true"));
+    }
+    response
+}
+
+async fn window_events(
+    options: EventOptions,
+    config: &Config,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let started = Instant::now();
+    let data = get_calendar_data(config).await?;
+    data_events(options, &data, started.elapsed(), config)
+}
+
+/// Responds with the events of the already fetched calendar data
+fn data_events(
+    mut options: EventOptions,
+    data: &CalendarData,
+    fetch_duration: Duration,
+    config: &Config,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    options.max_events = Some(config.max_events);
+    let (mut events, mut timings) = timed_data_to_events(&data.calendar, &data.spaces, &options)?;
+    let truncated = cap_events(&mut events, config.max_events);
+    let serialize_started = Instant::now();
+    let (json, content_type) = match (&options.fields, options.format) {
+        (Some(fields), ResponseFormat::Default) => (
+            warp::reply::json(&select_fields(&events, fields)),
+            "application/json",
+        ),
+        (None, ResponseFormat::Default) => (warp::reply::json(&events), "application/json"),
+        (fields, ResponseFormat::JsonApi) => {
+            // The resource id is needed even when not among the requested fields
+            let events = match fields {
+                Some(fields) => {
+                    select_fields(&events, &[fields.as_slice(), &["id".into()]].concat())
+                }
+                None => events
+                    .iter()
+                    .map(|event| serde_json::json!(event))
+                    .collect(),
+            };
+            (
+                warp::reply::json(&to_json_api(events)),
+                "application/vnd.api+json",
+            )
+        }
+    };
+    if options.record_timings {
+        timings.fetch_ms = millis(fetch_duration);
+        timings.parse_ms = LAST_PARSE_DURATION
+            .lock()
+            .ok()
+            .and_then(|duration| duration.map(millis));
+        timings.serialize_ms = millis(serialize_started.elapsed());
+        // Stored at once, so that concurrent requests don't mix their stages
+        if let Ok(mut last_timings) = LAST_TIMINGS.lock() {
+            *last_timings = Some(timings);
+        }
+    }
+    let reply = warp::reply::with_status(json, StatusCode::OK);
+    let reply = warp::reply::with_header(reply, "Content-Type", content_type);
+    let reply = warp::reply::with_header(reply, "Last-Modified", to_http_date(&data.fetched));
+    Ok(with_truncated_header(reply, truncated))
+}
+
+async fn events(
+    query: EventsQuery,
+    if_modified_since: Option<String>,
+    accept_language: Option<String>,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    // Respond in a timely manner even if some part of the pipeline stalls
+    let response = events_response(query, if_modified_since, accept_language, now, &config);
+    tokio::time::timeout(config.request_timeout, response)
+        .await
+        .unwrap_or_else(|_| {
+            Err(reject::custom(Error {
+                message: "504 - The request timed out".to_string(),
+                status: Some(StatusCode::GATEWAY_TIMEOUT),
+                ..Default::default()
+            }))
+        })
+}
+
+/// Number of events to return on one side of `around`, at most as many as a response may have
+fn around_count(
+    field: &str,
+    count: Option<usize>,
+    max_events: usize,
+) -> Result<usize, warp::Rejection> {
+    match count {
+        Some(count) if count > max_events => Err(reject::custom(Error::invalid_param(
+            field,
+            &format!("Parameter {field} must be at most {max_events}."),
+        ))),
+        count => Ok(count.unwrap_or(5)),
+    }
+}
+
+async fn events_response(
+    query: EventsQuery,
+    if_modified_since: Option<String>,
+    accept_language: Option<String>,
+    now: DateTime<Utc>,
+    config: &Config,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let around = match &query.around {
+        Some(_) if query.from.is_some() || query.to.is_some() => {
+            return Err(reject::custom(Error::invalid_param(
+                "around",
+                "Parameter around can't be combined with from and to.",
+            )));
+        }
+        Some(around) => Some(Around {
+            date: parse_date_param("around", around)?,
+            before: around_count("before", query.before, config.max_events)?,
+            after: around_count("after", query.after, config.max_events)?,
+        }),
+        None => None,
+    };
+    let window = match (&query.from, &query.to) {
+        // The events closest to the date are picked from all of them
+        (None, None) if around.is_some() => EventWindow::Range {
+            first: NaiveDate::from_ymd_opt(*PLAUSIBLE_YEARS.start(), 1, 1).unwrap_or_default(),
+            last: NaiveDate::from_ymd_opt(*PLAUSIBLE_YEARS.end(), 12, 31).unwrap_or_default(),
+        },
+        (None, None) if query.upcoming_only == Some(true) => EventWindow::NotStarted(now),
+        (None, None) => EventWindow::Upcoming(now),
+        (from, to) => range_window(from.as_deref(), to.as_deref(), now, config.timezone)?,
+    };
+    if let Some(source) = &query.source
+        && !config.has_calendar(source)
+    {
+        return Err(reject::custom(Error::bad_request(&format!(
+            "Unknown calendar source {source:?}."
+        ))));
+    }
+    let defaults = EventOptions::new(window, now, config);
+    let options = EventOptions {
+        // Events around a date are in time order
+        sort: match around {
+            Some(_) => SortKey::Start,
+            None => query.sort.unwrap_or_default(),
+        },
+        description: query.description.unwrap_or_default(),
+        legacy_date: query.legacy_date.unwrap_or(config.legacy_date),
+        language: query.lang,
+        fields: query.fields.map(|fields| {
+            fields
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .collect()
+        }),
+        raw: query.raw.unwrap_or_default(),
+        raw_times: query.raw_times.unwrap_or_default(),
+        expand_recurring: query.expand_recurring.unwrap_or(true),
+        merge_adjacent: query.merge_adjacent.unwrap_or_default(),
+        dedup: query.dedup.unwrap_or_default(),
+        featured: query.featured,
+        source: query.source.or(defaults.source.clone()),
+        clock: query
+            .clock
+            .or_else(|| {
+                accept_language
+                    .filter(|_| config.accept_language_clock)
+                    .and_then(|header| ClockFormat::from_accept_language(&header))
+            })
+            .unwrap_or(defaults.clock),
+        seconds: query.seconds.unwrap_or(defaults.seconds),
+        location: query.location.filter(|location| !location.is_empty()),
+        kind: query.kind.unwrap_or_default(),
+        format: query.format.unwrap_or_default(),
+        time_format: query.time_format.unwrap_or_default(),
+        around,
+        record_timings: config.debug_mode,
+        ..defaults
+    };
+
+    // Invalid queries are rejected even when the calendar hasn't changed
+    let started = Instant::now();
+    let data = get_calendar_data(config).await?;
+    if is_not_modified(if_modified_since.as_deref(), &data.fetched) {
+        let reply = warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED);
+        let reply = warp::reply::with_header(reply, "Last-Modified", to_http_date(&data.fetched));
+        return Ok(reply.into_response());
+    }
+    let mut response = data_events(options, &data, started.elapsed(), config)?;
+    if config.accept_language_clock {
+        // Shared caches must keep the responses for different languages apart
+        response.headers_mut().append(
+            "Vary",
+            warp::http::HeaderValue::from_static("Accept-Language"),
+        );
+    }
+    Ok(response)
+}
+
+async fn range_events(
+    start: String,
+    end: String,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    // Both bounds are given, thus the current time is only used for flagging live events
+    let window = range_window(Some(&start), Some(&end), now, config.timezone)?;
+    window_events(EventOptions::new(window, now, &config), &config).await
+}
+
+/// Range from Monday to Sunday of the ISO week containing the given day
+fn week_window(day: NaiveDate) -> EventWindow {
+    let week = day.week(chrono::Weekday::Mon);
+    EventWindow::Range {
+        first: week.first_day(),
+        last: week.last_day(),
+    }
+}
+
+async fn week_events(
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let window = week_window(now.with_timezone(&config.timezone).date_naive());
+    window_events(EventOptions::new(window, now, &config), &config).await
+}
+
+async fn next_week_events(
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let window = week_window(now.with_timezone(&config.timezone).date_naive() + Days::new(7));
+    window_events(EventOptions::new(window, now, &config), &config).await
+}
+
+/// Range of the days of the given month
+fn month_window(year: i32, month: u32) -> Result<EventWindow, warp::Rejection> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1).ok_or_else(|| {
+        reject::custom(Error::bad_request(
+            "The month must be given as a year and a month between 1 and 12.",
+        ))
+    })?;
+    Ok(EventWindow::Range {
+        first,
+        last: first + Months::new(1) - Days::new(1),
+    })
+}
+
+async fn month_events(
+    year: i32,
+    month: u32,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let window = month_window(year, month)?;
+    window_events(EventOptions::new(window, now, &config), &config).await
+}
+
+#[derive(Deserialize)]
+struct AgendaQuery {
+    /// Maximum number of events to include
+    limit: Option<usize>,
+}
+
+/// Groups events by the local day they start on, multi-day events are listed only under their first day
+fn group_by_day(events: Vec<Event>) -> BTreeMap<String, Vec<Event>> {
+    let mut days: BTreeMap<String, Vec<Event>> = BTreeMap::new();
+    for event in events {
+        let day = event.start_day.format("%Y-%m-%d").to_string();
+        days.entry(day).or_default().push(event);
+    }
+    days
+}
+
+/// Groups events by their lowercase categories, events without one under `uncategorized`.
+/// Events with several categories are listed under each of them.
+fn group_by_category(events: Vec<Event>) -> BTreeMap<String, Vec<Event>> {
+    let mut categories: BTreeMap<String, Vec<Event>> = BTreeMap::new();
+    for event in events {
+        let mut names: Vec<String> = event
+            .categories
+            .iter()
+            .map(|category| category.to_lowercase())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        if names.is_empty() {
+            names.push("uncategorized".to_string());
+        }
+        for name in names {
+            categories.entry(name).or_default().push(event.clone());
+        }
+    }
+    categories
+}
+
+async fn category_events(
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let data = get_calendar_data(&config).await?;
+    let mut options = EventOptions::new(EventWindow::Upcoming(now), now, &config);
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    let truncated = cap_events(&mut events, config.max_events);
+    let json = warp::reply::json(&group_by_category(events));
+    let reply = warp::reply::with_status(json, StatusCode::OK);
+    Ok(with_truncated_header(reply, truncated))
+}
+
+async fn agenda_events(
+    query: AgendaQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    if query.limit == Some(0) {
+        return Err(reject::custom(Error::bad_request(
+            "Parameter limit must be a positive integer.",
+        )));
+    }
+    let data = get_calendar_data(&config).await?;
+    let mut options = EventOptions::new(EventWindow::Upcoming(now), now, &config);
+    options.sort = SortKey::Start;
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    if let Some(limit) = query.limit {
+        events.truncate(limit);
+    }
+    let truncated = cap_events(&mut events, config.max_events);
+    let json = warp::reply::json(&group_by_day(events));
+    let reply = warp::reply::with_status(json, StatusCode::OK);
+    Ok(with_truncated_header(reply, truncated))
+}
+
+#[derive(Deserialize)]
+struct HistogramQuery {
+    /// Number of days from today to count events on, 30 by default
+    days: Option<u64>,
+}
+
+/// Upper bound of the `days` of `/events/histogram`
+const MAX_HISTOGRAM_DAYS: u64 = 366;
+
+/// Number of events taking place on a day
+#[derive(Serialize, Debug, PartialEq)]
+struct HistogramDay {
+    /// iso8601 date, e.g. `2026-02-03`
+    date: String,
+    count: usize,
+}
+
+/// Counts the events taking place on each of the days from the first one, multi-day events
+/// counting on every day they cover
+fn day_histogram(events: &[Event], first: NaiveDate, days: u64) -> Vec<HistogramDay> {
+    let mut counts = vec![0; days as usize];
+    for event in events {
+        for (index, count) in counts.iter_mut().enumerate() {
+            let day = first + Days::new(index as u64);
+            if event.start_day <= day && day <= event.last_day {
+                *count += 1;
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(index, count)| HistogramDay {
+            date: (first + Days::new(index as u64))
+                .format("%Y-%m-%d")
+                .to_string(),
+            count,
+        })
+        .collect()
+}
+
+async fn histogram_events(
+    query: HistogramQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let days = query.days.unwrap_or(30);
+    if !(1..=MAX_HISTOGRAM_DAYS).contains(&days) {
+        return Err(reject::custom(Error::bad_request(&format!(
+            "Parameter days must be between 1 and {MAX_HISTOGRAM_DAYS}."
+        ))));
+    }
+    let data = get_calendar_data(&config).await?;
+    let first = now.with_timezone(&config.timezone).date_naive();
+    let window = EventWindow::Range {
+        first,
+        last: first + Days::new(days - 1),
+    };
+    let mut options = EventOptions::new(window, now, &config);
+    options.description = DescriptionMode::None;
+    let events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    let histogram = day_histogram(&events, first, days);
+    let reply = warp::reply::with_status(warp::reply::json(&histogram), StatusCode::OK);
+    Ok(warp::reply::with_header(
+        reply,
+        "Last-Modified",
+        to_http_date(&data.fetched),
+    ))
+}
+
+#[derive(Deserialize)]
+struct IndexQuery {
+    /// Maximum number of events to include, 500 by default
+    limit: Option<usize>,
+}
+
+/// Minimal event for building a search index on the client
+#[derive(Serialize, Debug)]
+struct IndexEntry {
+    id: String,
+    summary: String,
+    /// iso8601 formatted start, like `start_iso8601` of the event
+    start: String,
+}
+
+async fn index_events(
+    query: IndexQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(500);
+    if limit == 0 {
+        return Err(reject::custom(Error::bad_request(
+            "Parameter limit must be a positive integer.",
+        )));
+    }
+    let data = get_calendar_data(&config).await?;
+    let mut options = EventOptions::new(EventWindow::Upcoming(now), now, &config);
+    options.sort = SortKey::Start;
+    options.description = DescriptionMode::None;
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    events.truncate(limit);
+    let truncated = cap_events(&mut events, config.max_events);
+    let entries: Vec<IndexEntry> = events
+        .into_iter()
+        .map(|event| IndexEntry {
+            id: event.id,
+            summary: event.summary,
+            start: event.start_iso8601,
+        })
+        .collect();
+    let reply = warp::reply::with_status(warp::reply::json(&entries), StatusCode::OK);
+    let reply = warp::reply::with_header(reply, "Last-Modified", to_http_date(&data.fetched));
+    Ok(with_truncated_header(reply, truncated))
+}
+
+#[derive(Deserialize)]
+struct BatchQuery {
+    /// Comma separated amounts of events, e.g. `3,10`
+    counts: String,
+}
+
+fn parse_counts(counts: &str) -> Result<Vec<usize>, warp::Rejection> {
+    counts
+        .split(',')
+        .map(|count| count.trim().parse().ok().filter(|count| *count > 0))
+        .collect::<Option<_>>()
+        .ok_or_else(|| {
+            reject::custom(Error::bad_request(
+                "Parameter counts must be a comma separated list of positive integers.",
+            ))
+        })
+}
+
+/// Slices of the upcoming events keyed by their requested amounts
+fn batch_slices(events: &[Event], counts: &[usize]) -> BTreeMap<String, Vec<Event>> {
+    counts
+        .iter()
+        .map(|&count| {
+            let slice = events.iter().take(count).cloned().collect();
+            (count.to_string(), slice)
+        })
+        .collect()
+}
+
+async fn batch_events(
+    query: BatchQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let counts = parse_counts(&query.counts)?;
+    let data = get_calendar_data(&config).await?;
+    let mut options = EventOptions::new(EventWindow::Upcoming(now), now, &config);
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    let truncated = cap_events(&mut events, config.max_events);
+    let json = warp::reply::json(&batch_slices(&events, &counts));
+    let reply = warp::reply::with_status(json, StatusCode::OK);
+    Ok(with_truncated_header(reply, truncated))
+}
+
+/// Event in the shape of FullCalendar's event objects
+#[skip_serializing_none]
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FullCalendarEvent {
+    id: String,
+    title: String,
+    start: String,
+    end: String,
+    all_day: bool,
+    url: Option<String>,
+}
+
+impl From<Event> for FullCalendarEvent {
+    fn from(event: Event) -> Self {
+        FullCalendarEvent {
+            id: event.id,
+            title: event.summary,
+            start: event.start_iso8601,
+            end: event.end_iso8601,
+            all_day: event.when.all_day,
+            url: event.url,
+        }
+    }
+}
+
+/// Range FullCalendar requests events for, its end being exclusive
+#[derive(Deserialize)]
+struct FullCalendarQuery {
+    /// ISO 8601 date or timestamp, of which only the date is considered
+    start: Option<String>,
+    end: Option<String>,
+}
+
+fn fullcalendar_window(
+    query: &FullCalendarQuery,
+    now: DateTime<Utc>,
+) -> Result<EventWindow, warp::Rejection> {
+    let date = |name, value: &str| parse_date_param(name, value.get(..10).unwrap_or(value));
+    match (&query.start, &query.end) {
+        (Some(start), Some(end)) => Ok(EventWindow::Range {
+            first: date("start", start)?,
+            last: date("end", end)? - Days::new(1),
+        }),
+        (None, None) => Ok(EventWindow::Upcoming(now)),
+        _ => Err(reject::custom(Error::bad_request(
+            "Parameters start and end must be given together.",
+        ))),
+    }
+}
+
+async fn fullcalendar_events(
+    query: FullCalendarQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let window = fullcalendar_window(&query, now)?;
+    let data = get_calendar_data(&config).await?;
+    let mut options = EventOptions::new(window, now, &config);
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&data.calendar, &data.spaces, &options)?;
+    let truncated = cap_events(&mut events, config.max_events);
+    let events: Vec<FullCalendarEvent> = events.into_iter().map(FullCalendarEvent::from).collect();
+    let reply = warp::reply::with_status(warp::reply::json(&events), StatusCode::OK);
+    Ok(with_truncated_header(reply, truncated))
+}
+
+#[derive(Deserialize)]
+struct BetweenQuery {
+    /// RFC 3339 timestamp, unbounded when unset
+    start: Option<String>,
+    /// RFC 3339 timestamp, unbounded when unset
+    end: Option<String>,
+    inclusive: Option<Inclusive>,
+}
+
+fn parse_timestamp_param(name: &str, value: &str) -> Result<DateTime<Utc>, warp::Rejection> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.to_utc())
+        .map_err(|_| {
+            reject::custom(Error::bad_request(&format!(
+                "Parameter {name} must be a RFC 3339 timestamp."
+            )))
+        })
+}
+
+fn between_window(query: &BetweenQuery) -> Result<EventWindow, warp::Rejection> {
+    let start = query
+        .start
+        .as_deref()
+        .map(|start| parse_timestamp_param("start", start))
+        .transpose()?;
+    let end = query
+        .end
+        .as_deref()
+        .map(|end| parse_timestamp_param("end", end))
+        .transpose()?;
+    if let (Some(start), Some(end)) = (start, end)
+        && start > end
+    {
+        return Err(reject::custom(Error::bad_request(
+            "Parameter start must not be after end.",
+        )));
+    }
+    Ok(EventWindow::Between {
+        start,
+        end,
+        inclusive: query.inclusive.unwrap_or_default(),
+    })
+}
+
+async fn between_events(
+    query: BetweenQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let mut options = EventOptions::new(between_window(&query)?, now, &config);
+    options.sort = SortKey::Start;
+    window_events(options, &config).await
+}
+
+#[derive(Deserialize)]
+struct PastQuery {
+    /// RFC 3339 timestamp, events which ended before it are excluded
+    since: Option<String>,
+}
+
+async fn past_events(
+    query: PastQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    let since = match query.since {
+        Some(since) => parse_timestamp_param("since", &since)?,
+        None => now - Days::new(config.past_max_days),
+    };
+    let window = EventWindow::Past { since, until: now };
+    window_events(EventOptions::new(window, now, &config), &config).await
+}
+
+/// Provides handlers with the configuration
+fn with_config(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (Arc<Config>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
+/// Current time from the clock, overridable with the `X-Debug-Now` header when `DEBUG_MODE` is enabled
+fn current_time(
+    clock: &dyn Clock,
+    debug_now: Option<&str>,
+    debug_mode: bool,
+) -> Result<DateTime<Utc>, warp::Rejection> {
+    match debug_now {
+        Some(debug_now) if debug_mode => DateTime::parse_from_rfc3339(debug_now)
+            .map(|debug_now| debug_now.to_utc())
+            .map_err(|_| {
+                reject::custom(Error::bad_request(
+                    "Header X-Debug-Now must be a RFC 3339 timestamp.",
+                ))
+            }),
+        _ => Ok(clock.now()),
+    }
+}
+
+/// Provides handlers with the current time, see `current_time`
+fn with_now(
+    config: Arc<Config>,
+    clock: Arc<dyn Clock>,
+) -> impl Filter<Extract = (DateTime<Utc>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("x-debug-now").and_then(move |debug_now: Option<String>| {
+        let now = current_time(clock.as_ref(), debug_now.as_deref(), config.debug_mode);
+        async move { now }
+    })
+}
+
+async fn series(now: DateTime<Utc>, config: Arc<Config>) -> Result<impl Reply, warp::Rejection> {
+    let data = get_calendar_data(&config).await?;
+    let json = warp::reply::json(&data_to_series(&data.calendar, now));
+    Ok(warp::reply::with_status(json, StatusCode::OK))
+}
+
+/// Server-sent events of the upcoming events, a snapshot followed by the refreshed events on each change
+async fn stream_events(
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    // Only the background refresh broadcasts changes, without it streams would go silent
+    if config.background_refresh.is_none() {
+        return Err(reject::custom(Error {
+            message: "404 - Event streams require BACKGROUND_REFRESH_SECS to be set".to_string(),
+            status: Some(StatusCode::NOT_FOUND),
+            ..Default::default()
+        }));
+    }
+    // Subscribe before taking the snapshot so that no change is missed in between
+    let receiver = EVENT_UPDATES.subscribe();
+    let data = get_calendar_data(&config).await?;
+    let snapshot = upcoming_events_json(&data, &config, now)?;
+    let updates = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(payload) => return Some((payload, receiver)),
+                // Skipped payloads are outdated by the ones that follow
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = futures_util::stream::once(async { snapshot })
+        .chain(updates)
+        .map(|payload| {
+            Ok::<_, std::convert::Infallible>(
+                warp::sse::Event::default().event("events").data(payload),
+            )
+        });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+#[derive(Deserialize)]
+struct PreviewQuery {
+    /// http(s) URL of the previewed calendar
+    url: String,
+}
+
+/// Largest previewed calendar in bytes
+const MAX_PREVIEW_BYTES: usize = 5 * 1024 * 1024;
+
+/// Whether the address is reachable on the public internet, as opposed to private,
+/// loopback, link-local or otherwise special addresses of the local network
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, ..] = address.octets();
+            !(address.is_private()
+                || address.is_loopback()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_multicast()
+                || address.is_documentation()
+                // "This network", carrier-grade NAT, benchmarking and reserved
+                || first == 0
+                || (first == 100 && (64..128).contains(&second))
+                || (first == 198 && (18..20).contains(&second))
+                || first >= 240)
+        }
+        IpAddr::V6(address) => match embedded_ipv4(address) {
+            Some(address) => is_public_address(IpAddr::V4(address)),
+            None => {
+                let first_segment = address.segments()[0];
+                !(address.is_loopback()
+                    || address.is_unspecified()
+                    || address.is_multicast()
+                    // Unique local and link-local addresses
+                    || (first_segment & 0xfe00) == 0xfc00
+                    || (first_segment & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// IPv4 address carried by an IPv4-mapped, IPv4-compatible, NAT64 or 6to4 address, which
+/// reach the IPv4 address and so must be checked as one
+fn embedded_ipv4(address: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = address.segments();
+    let from_segments =
+        |high: u16, low: u16| Some(Ipv4Addr::from((high as u32) << 16 | low as u32));
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low]
+        | [0, 0, 0, 0, 0, 0, high, low]
+        | [0x64, 0xff9b, 0, 0, 0, 0, high, low]
+        | [0x2002, high, low, ..] => from_segments(high, low),
+        _ => None,
+    }
+}
+
+/// Fetches a calendar from an untrusted URL, connecting only to the public address its host
+/// resolves to so that the preview can't be used to reach the local network
+async fn fetch_preview_calendar(config: &Config, url: &str) -> Result<String, warp::Rejection> {
+    let not_allowed = || {
+        reject::custom(Error::bad_request(
+            "Parameter url must be a http(s) URL of a public host.",
+        ))
+    };
+    let url = reqwest::Url::parse(url).map_err(|_| not_allowed())?;
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err(not_allowed());
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(not_allowed());
+    }
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|_| not_allowed())?
+        .collect();
+    if addresses.is_empty()
+        || !addresses
+            .iter()
+            .all(|address| is_public_address(address.ip()))
+    {
+        return Err(not_allowed());
+    }
+
+    let upstream_error = |err: reqwest::Error| {
+        reject::custom(Error {
+            message: "502 - The calendar could not be fetched".to_string(),
+            details: Some(err.without_url().to_string()),
+            status: Some(StatusCode::BAD_GATEWAY),
+            ..Default::default()
+        })
+    };
+    // Pinning the checked address prevents the host from resolving elsewhere for the request,
+    // and redirects could lead anywhere
+    let client = reqwest::Client::builder()
+        .resolve(host, addresses[0])
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy()
+        .timeout(config.request_timeout)
+        .build()
+        .map_err(upstream_error)?;
+    let mut response = client.get(url).send().await.map_err(upstream_error)?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(String::from);
+    let mut calendar_bytes = vec![];
+    while let Some(chunk) = response.chunk().await.map_err(upstream_error)? {
+        if calendar_bytes.len() + chunk.len() > MAX_PREVIEW_BYTES {
+            return Err(reject::custom(Error {
+                message: "502 - The calendar is too large to preview".to_string(),
+                status: Some(StatusCode::BAD_GATEWAY),
+                ..Default::default()
+            }));
+        }
+        calendar_bytes.extend_from_slice(&chunk);
+    }
+    Ok(decode_calendar(&calendar_bytes, content_type.as_deref()))
+}
+
+/// Upcoming events of a calendar at an arbitrary URL, when enabled with `ALLOW_ARBITRARY_URLS`
+async fn preview_events(
+    query: PreviewQuery,
+    now: DateTime<Utc>,
+    config: Arc<Config>,
+) -> Result<impl Reply, warp::Rejection> {
+    if !config.allow_arbitrary_urls {
+        return Err(reject::not_found());
+    }
+    let calendar_data = fetch_preview_calendar(&config, &query.url).await?;
+    let calendar = non_empty_calendar(calendar_data)
+        .and_then(process_calendar)
+        .map_err(|err| {
+            reject::custom(Error {
+                message: "502 - The calendar could not be processed".to_string(),
+                details: Some(format!("{}: {err:?}", redact_url(&query.url))),
+                status: Some(StatusCode::BAD_GATEWAY),
+                ..Default::default()
+            })
+        })?;
+    let mut options = EventOptions::new(EventWindow::Upcoming(now), now, &config);
+    options.max_events = Some(config.max_events);
+    let mut events = data_to_events(&calendar, &[], &options)?;
+    let truncated = cap_events(&mut events, config.max_events);
+    let reply = warp::reply::with_status(warp::reply::json(&events), StatusCode::OK);
+    Ok(with_truncated_header(reply, truncated))
+}
+
+/// Operational state of the calendar cache
+#[derive(Serialize, Debug)]
+struct Health {
+    /// `ok`, `stale` when the calendar is older than the cache TTL, or `unavailable`
+    status: &'static str,
+    /// When the calendar was last fetched with changes
+    last_fetched: Option<String>,
+    /// When the upstream last confirmed the calendar
+    last_checked: Option<String>,
+    /// Seconds since the upstream last confirmed the calendar
+    cache_age_secs: Option<i64>,
+    /// Number of events in the calendar, recurring series counted once
+    event_count: Option<usize>,
+    last_error: Option<FetchError>,
+}
+
+fn health_report(
+    data: Option<&CalendarData>,
+    last_error: Option<FetchError>,
+    now: DateTime<Utc>,
+    cache_ttl: Duration,
+) -> Health {
+    let timestamp =
+        |date_time: DateTime<Utc>| date_time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let cache_age_secs = data.map(|data| (now - data.checked).num_seconds());
+    Health {
+        status: match cache_age_secs {
+            None => "unavailable",
+            Some(age) if age > cache_ttl.as_secs() as i64 => "stale",
+            Some(_) => "ok",
+        },
+        last_fetched: data.map(|data| timestamp(data.fetched)),
+        last_checked: data.map(|data| timestamp(data.checked)),
+        cache_age_secs,
+        event_count: data.map(|data| data.calendar.events().count()),
+        last_error,
+    }
+}
+
+/// Reports the cached state without fetching the calendar
+async fn health(now: DateTime<Utc>, config: Arc<Config>) -> Result<impl Reply, warp::Rejection> {
+    let data = last_good_calendar_data();
+    let last_error = LAST_FETCH_ERROR
+        .lock()
+        .ok()
+        .and_then(|last_error| last_error.clone());
+    let report = health_report(data.as_deref(), last_error, now, config.cache_ttl);
+    let status = match data {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&report), status))
+}
+
+/// Reports the stage durations of the latest `/events` request, only in `DEBUG_MODE`
+async fn debug_timing(config: Arc<Config>) -> Result<impl Reply, warp::Rejection> {
+    if !config.debug_mode {
+        return Err(reject::not_found());
+    }
+    let timings = LAST_TIMINGS.lock().ok().and_then(|timings| timings.clone());
+    Ok(warp::reply::json(&timings))
+}
+
+/// Readiness of the instance to serve events, for load balancers
+#[derive(Serialize, Debug)]
+struct Readiness {
+    ready: bool,
+}
+
+/// Reports whether the calendar has been fetched, with `503 Service Unavailable` until it has
+async fn readiness(config: Arc<Config>) -> Result<impl Reply, warp::Rejection> {
+    if !READY.load(atomic::Ordering::Relaxed) && config.background_refresh.is_none() {
+        // Nothing else warms the cache before the load balancer lets requests through
+        let _ = get_calendar_data(&config).await;
+    }
+    let ready = READY.load(atomic::Ordering::Relaxed);
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&Readiness { ready }),
+        status,
+    ))
+}
+
+async fn info(config: Arc<Config>) -> Result<impl Reply, warp::Rejection> {
+    let data = get_calendar_data(&config).await?;
+    let json = warp::reply::json(&calendar_info(&data.calendar));
+    Ok(warp::reply::with_status(json, StatusCode::OK))
+}
+
+/// Readiness probe, which load balancers call without signing their requests
+pub fn readiness_filter(config: Arc<Config>) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("health" / "ready")
+        .and(with_config(config))
+        .and_then(readiness)
+        .boxed()
+}
+
+pub fn filter(config: Arc<Config>, clock: Arc<dyn Clock>) -> BoxedFilter<(impl Reply,)> {
+    let timezone = config.timezone;
+    // The extension is for clients which can't easily set an Accept header
+    let upcoming = warp::path("events")
+        .or(warp::path("events.json"))
+        .unify()
+        .and(warp::path::end())
+        .and(warp::query::<EventsQuery>())
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .and(warp::header::optional::<String>("accept-language"))
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(events);
+    let past = warp::path!("events" / "past")
+        .and(warp::query::<PastQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(past_events);
+    let range = warp::path!("events" / "range" / String / String)
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(range_events);
+    let agenda = warp::path!("events" / "agenda")
+        .and(warp::query::<AgendaQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(agenda_events);
+    let by_category = warp::path!("events" / "by-category")
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(category_events);
+    let histogram = warp::path!("events" / "histogram")
+        .and(warp::query::<HistogramQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(histogram_events);
+    let index = warp::path!("events" / "index")
+        .and(warp::query::<IndexQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(index_events);
+    let batch = warp::path!("events" / "batch")
+        .and(warp::query::<BatchQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(batch_events);
+    let fullcalendar = warp::path!("events" / "fullcalendar")
+        .and(warp::query::<FullCalendarQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(fullcalendar_events);
+    let between = warp::path!("events" / "between")
+        .and(warp::query::<BetweenQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(between_events);
+    let stream = warp::path!("events" / "stream")
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(stream_events);
+    let week = warp::path!("events" / "week")
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(week_events);
+    let next_week = warp::path!("events" / "week" / "next")
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(next_week_events);
+    let month = warp::path!("events" / "month" / i32 / u32)
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(month_events);
+    let preview = warp::path!("events" / "preview")
+        .and(warp::query::<PreviewQuery>())
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(preview_events);
+    let series = warp::path!("series")
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(series);
+    let health = warp::path!("health")
+        .and(with_now(config.clone(), clock.clone()))
+        .and(with_config(config.clone()))
+        .and_then(health);
+    let timing = warp::path!("debug" / "timing")
+        .and(with_config(config.clone()))
+        .and_then(debug_timing);
+    let info = warp::path!("calendar" / "info")
+        .and(with_config(config))
+        .and_then(info);
+    upcoming
+        .or(past)
+        .or(range)
+        .or(agenda)
+        .or(by_category)
+        .or(histogram)
+        .or(index)
+        .or(batch)
+        .or(fullcalendar)
+        .or(between)
+        .or(stream)
+        .or(week)
+        .or(next_week)
+        .or(month)
+        .or(preview)
+        .or(series)
+        .or(health)
+        .or(timing)
+        .or(info)
+        .map(move |reply| warp::reply::with_header(reply, "X-Timezone", timezone.name()))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 2, 2, 16, 32, 11).unwrap()
+    }
+
+    #[test]
+    fn test_event_parsing() {
+        let calendar_data: &'static str = include_str!("test-data/basic.ics");
+        let now = now();
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let result = data_to_events(
+            &calendar,
+            &[],
+            &EventOptions::new(EventWindow::Upcoming(now), now, &Config::default()),
+        )
+        .unwrap();
+        assert_matches!(&result[..], [Event {
+            summary, description: Some(description),
+            location: Some(Location{string: location_string, url: _}),
+            color: Some(color),
+            recurring: false,
+            recurrence_id: None,
+            ..
+        }] if summary == "Test Event"
+            && description == "Test description"
+            && location_string == "Test Location"
+            && color == "turquoise");
+    }
+
+    #[test]
+    fn test_point_in_time() {
+        let start = Utc.with_ymd_and_hms(2026, 2, 3, 12, 0, 0).unwrap();
+        let mut calendar = Calendar::new();
+        calendar.push(
+            icalendar::Event::new()
+                .summary("Deadline")
+                .starts(start)
+                .ends(start)
+                .done(),
+        );
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        options.timezone = Tz::Europe__Helsinki;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { date: Some(date), duration_minutes: 0, .. }]
+            if date == "03/02/2026 14:00");
+    }
+
+    #[test]
+    fn test_output_timezone() {
+        let calendar = single_event_calendar(
+            "UID:meeting@example.com
+            SUMMARY:Meeting
+            DTSTART:20260203T160000Z
+            DTEND:20260203T170000Z",
+        );
+        let config = Config {
+            timezone: Tz::America__New_York,
+            ..Config::default()
+        };
+        let options = EventOptions::new(range((2026, 2, 1), (2026, 2, 28)), now(), &config);
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event {
+            date: Some(date),
+            when: When { start: Timestamp::Formatted(start), timezone: Some(timezone), .. },
+            ..
+        }] if date == "03/02/2026 11:00 - 12:00"
+            && start == "2026-02-03T11:00:00-05:00"
+            && timezone == "America/New_York");
+    }
+
+    #[test]
+    fn test_clock_format() {
+        let calendar = Calendar::from_str(include_str!("test-data/duration.ics")).unwrap();
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        options.clock = ClockFormat::H12;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        let date = result[0].date.as_deref().unwrap();
+        assert!(date.starts_with("03/02/2026 "));
+        assert!(date.ends_with("M") && date.contains("M - "));
+    }
+
+    #[test]
+    fn test_time_seconds() {
+        let calendar = Calendar::from_str(include_str!("test-data/duration.ics")).unwrap();
+        let config = Config {
+            timezone: Tz::UTC,
+            time_seconds: true,
+            ..Config::default()
+        };
+        let mut options = EventOptions::new(EventWindow::Upcoming(now()), now(), &config);
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_eq!(
+            result[0].date.as_deref(),
+            Some("03/02/2026 16:00:00 - 17:00:00")
+        );
+        options.clock = ClockFormat::H12;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_eq!(
+            result[0].date.as_deref(),
+            Some("03/02/2026 04:00:00 PM - 05:00:00 PM")
+        );
+    }
+
+    #[test]
+    fn test_duration_parsing() {
+        let calendar_data: &'static str = include_str!("test-data/duration.ics");
+        let now = now();
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let options = EventOptions::new(EventWindow::Upcoming(now), now, &Config::default());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event {
+            summary,
+            start_iso8601,
+            end_iso8601,
+            ..
+        }] if summary == "Duration Event"
+            && start_iso8601 == "2026-02-03T16:00:00Z"
+            && end_iso8601 == "2026-02-03T17:00:00Z");
+        // Durations past the supported dates are skipped rather than overflowing
+        for start in ["DTSTART;VALUE=DATE:20260203", "DTSTART:20260203T160000Z"] {
+            let calendar = single_event_calendar(&format!(
+                "UID:forever@example.com
+                SUMMARY:Forever
+                {start}
+                DURATION:P99999999D"
+            ));
+            assert!(data_to_events(&calendar, &[], &options).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_structured_dates() {
+        let calendar_data: &'static str = include_str!("test-data/basic.ics");
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        options.legacy_date = false;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event {
+            date: None,
+            when: When {
+                start: Timestamp::Formatted(start),
+                end: Timestamp::Formatted(end),
+                all_day: true,
+                timezone: None,
+            },
+            ..
+        }] if start == "2026-02-03" && end == "2026-02-04");
+        options.time_format = TimeFormat::EpochMs;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_eq!(result[0].when.start, Timestamp::EpochMillis(1770076800000));
+        assert_eq!(result[0].when.end, Timestamp::EpochMillis(1770163200000));
+    }
+
+    #[test]
+    fn test_past_event_parsing() {
+        let calendar_data: &'static str = include_str!("test-data/basic.ics");
+        let now = now();
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let window = EventWindow::Past {
+            since: now - Days::new(90),
+            until: now,
+        };
+        let result = data_to_events(
+            &calendar,
+            &[],
+            &EventOptions::new(window, now, &Config::default()),
+        )
+        .unwrap();
+        assert_matches!(&result[..], [Event { summary, .. }] if summary == "Past Event");
+    }
+
+    #[test]
+    fn test_moved_occurrence() {
+        let calendar = Calendar::from_str(include_str!("test-data/override.ics")).unwrap();
+        let window = EventWindow::Range {
+            first: NaiveDate::from_ymd_opt(2026, 2, 1).unwrap(),
+            last: NaiveDate::from_ymd_opt(2026, 4, 30).unwrap(),
+        };
+        let options = EventOptions::new(window, now(), &Config::default());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        let starts: Vec<&str> = result
+            .iter()
+            .map(|event| event.start_iso8601.as_str())
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                "2026-02-03T16:00:00Z",
+                "2026-03-05T16:00:00Z",
+                "2026-04-07T16:00:00Z"
+            ]
+        );
+        assert_eq!(result[1].summary, "Monthly meeting (moved)");
+    }
+
+    #[test]
+    fn test_unexpanded_series() {
+        let calendar = Calendar::from_str(include_str!("test-data/override.ics")).unwrap();
+        let window = EventWindow::Range {
+            first: NaiveDate::from_ymd_opt(2026, 4, 1).unwrap(),
+            last: NaiveDate::from_ymd_opt(2026, 4, 30).unwrap(),
+        };
+        let mut options = EventOptions::new(window, now(), &Config::default());
+        options.expand_recurring = false;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { start_iso8601, rrule: Some(rrule), .. }]
+            if start_iso8601 == "2026-02-03T16:00:00Z" && rrule == "FREQ=MONTHLY;BYDAY=1TU;COUNT=3");
+    }
+
+    #[test]
+    fn test_range_parsing() {
+        let calendar_data: &'static str = include_str!("test-data/recurrence.ics");
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let window = EventWindow::Range {
+            first: NaiveDate::from_ymd_opt(2026, 3, 1).unwrap(),
+            last: NaiveDate::from_ymd_opt(2026, 4, 30).unwrap(),
+        };
+        let result = data_to_events(
+            &calendar,
+            &[],
+            &EventOptions::new(window, now(), &Config::default()),
+        )
+        .unwrap();
+        assert_matches!(
+            &result[..],
+            [Event { date: Some(date1), .. }, Event { date: Some(date2), .. }]
+                if date1 == "02/03/2026" && date2 == "06/04/2026"
+        );
+    }
+
+    #[test]
+    fn test_day_histogram() {
+        let calendar = Calendar::from_str(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:camp@example.com\r\nSUMMARY:Camp\r\n\
+            DTSTART;VALUE=DATE:20260203\r\nDTEND;VALUE=DATE:20260205\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:meeting@example.com\r\nSUMMARY:Meeting\r\n\
+            DTSTART:20260204T160000Z\r\nDTEND:20260204T170000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n",
+        )
+        .unwrap();
+        let config = Config {
+            timezone: Tz::UTC,
+            ..Config::default()
+        };
+        let options = EventOptions::new(range((2026, 2, 2), (2026, 2, 6)), now(), &config);
+        let events = data_to_events(&calendar, &[], &options).unwrap();
+        let first = NaiveDate::from_ymd_opt(2026, 2, 2).unwrap();
+        let counts: Vec<_> = day_histogram(&events, first, 5)
+            .iter()
+            .map(|day| day.count)
+            .collect();
+        assert_eq!(counts, [0, 1, 2, 0, 0]);
+        assert_eq!(day_histogram(&events, first, 1)[0].date, "2026-02-02");
+    }
+
+    #[test]
+    fn test_comma_joined_categories() {
+        let calendar = process_calendar(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:party@example.com\r\n\
+            CATEGORIES:party,social\r\nCATEGORIES;LANGUAGE=en:Food\\, drinks,\r\n\
+            END:VEVENT\r\nEND:VCALENDAR\r\n"
+                .to_string(),
+        )
+        .unwrap();
+        let event = calendar.events().next().unwrap();
+        assert_eq!(event_categories(event), ["party", "social", "Food, drinks"]);
+    }
+
+    #[test]
+    fn test_category_grouping() {
+        let calendar = process_calendar(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:sauna@example.com\r\nSUMMARY:Sauna meeting\r\n\
+            CATEGORIES:Sauna,Meeting\r\n\
+            DTSTART:20260203T160000Z\r\nDTEND:20260203T170000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:other@example.com\r\nSUMMARY:Other\r\n\
+            DTSTART:20260204T160000Z\r\nDTEND:20260204T170000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n"
+                .to_string(),
+        )
+        .unwrap();
+        let options = EventOptions::new(
+            range((2026, 2, 1), (2026, 2, 28)),
+            now(),
+            &Config::default(),
+        );
+        let categories = group_by_category(data_to_events(&calendar, &[], &options).unwrap());
+        assert_eq!(
+            categories.keys().collect::<Vec<_>>(),
+            ["meeting", "sauna", "uncategorized"]
+        );
+        assert_eq!(categories["sauna"][0].summary, "Sauna meeting");
+        assert_eq!(categories["uncategorized"][0].summary, "Other");
+    }
+
+    #[test]
+    fn test_featured_events() {
+        let calendar = process_calendar(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:gala@example.com\r\nSUMMARY:Gala\r\n\
+            CATEGORIES:party,Featured\r\n\
+            DTSTART:20260203T160000Z\r\nDTEND:20260203T170000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:launch@example.com\r\nSUMMARY:Launch\r\nX-FEATURED:TRUE\r\n\
+            DTSTART:20260204T160000Z\r\nDTEND:20260204T170000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:sauna@example.com\r\nSUMMARY:Sauna\r\nX-FEATURED:FALSE\r\n\
+            DTSTART:20260205T160000Z\r\nDTEND:20260205T170000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n"
+                .to_string(),
+        )
+        .unwrap();
+        let mut options = EventOptions::new(
+            range((2026, 2, 1), (2026, 2, 28)),
+            now(),
+            &Config::default(),
+        );
+        let featured: Vec<_> = data_to_events(&calendar, &[], &options)
+            .unwrap()
+            .iter()
+            .map(|event| event.featured)
+            .collect();
+        assert_eq!(featured, [true, true, false]);
+        options.featured = Some(true);
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { summary: a, .. }, Event { summary: b, .. }]
+            if a == "Gala" && b == "Launch");
+    }
+
+    #[test]
+    fn test_max_events() {
+        let calendar = Calendar::from_str(include_str!("test-data/recurrence.ics")).unwrap();
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        assert_eq!(data_to_events(&calendar, &[], &options).unwrap().len(), 12);
+        // Conversion stops one event past the cap, which tells that events were left out
+        options.max_events = Some(3);
+        let mut events = data_to_events(&calendar, &[], &options).unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(cap_events(&mut events, 3));
+        assert_matches!(&events[..], [_, _, Event { date: Some(date), .. }] if date == "06/04/2026");
+        options.max_events = Some(12);
+        let mut events = data_to_events(&calendar, &[], &options).unwrap();
+        assert!(!cap_events(&mut events, 12));
+    }
+
+    #[test]
+    fn test_summary_dedup() {
+        let calendar = Calendar::from_str(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:first@example.com\r\nSUMMARY:Sauna\r\n\
+            DTSTART:20260203T160000Z\r\nDTEND:20260203T170000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:copy@example.com\r\nSUMMARY:Sauna\r\n\
+            DTSTART:20260203T160000Z\r\nDTEND:20260203T170000Z\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:longer@example.com\r\nSUMMARY:Sauna\r\n\
+            DTSTART:20260203T160000Z\r\nDTEND:20260203T180000Z\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n",
+        )
+        .unwrap();
+        let mut options = EventOptions::new(
+            range((2026, 2, 1), (2026, 2, 28)),
+            now(),
+            &Config::default(),
+        );
+        assert_eq!(data_to_events(&calendar, &[], &options).unwrap().len(), 3);
+        options.dedup = true;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_ne!(result[0].end_iso8601, result[1].end_iso8601);
+    }
+
+    #[test]
+    fn test_merge_adjacent() {
+        let calendar = Calendar::from_str(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\nUID:day1@example.com\r\nSUMMARY:Festival\r\n\
+            DTSTART;VALUE=DATE:20260310\r\nDTEND;VALUE=DATE:20260311\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:day2@example.com\r\nSUMMARY:Festival\r\n\
+            DTSTART;VALUE=DATE:20260311\r\nDTEND;VALUE=DATE:20260313\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:later@example.com\r\nSUMMARY:Festival\r\n\
+            DTSTART;VALUE=DATE:20260320\r\nDTEND;VALUE=DATE:20260321\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:market@example.com\r\nSUMMARY:Market\r\n\
+            DTSTART;VALUE=DATE:20260311\r\nDTEND;VALUE=DATE:20260312\r\nEND:VEVENT\r\n\
+            END:VCALENDAR\r\n",
+        )
+        .unwrap();
+        let mut options = EventOptions::new(
+            range((2026, 3, 1), (2026, 3, 31)),
+            now(),
+            &Config::default(),
+        );
+        assert_eq!(data_to_events(&calendar, &[], &options).unwrap().len(), 4);
+
+        options.merge_adjacent = true;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        let dates: Vec<_> = result
+            .iter()
+            .map(|event| {
+                (
+                    event.summary.as_str(),
+                    event.start_iso8601.as_str(),
+                    event.end_iso8601.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            dates,
+            [
+                ("Market", "2026-03-11", "2026-03-12"),
+                ("Festival", "2026-03-10", "2026-03-13"),
+                ("Festival", "2026-03-20", "2026-03-21"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_agenda_grouping() {
+        let calendar_data: &'static str = include_str!("test-data/recurrence.ics");
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let window = EventWindow::Range {
+            first: NaiveDate::from_ymd_opt(2026, 3, 1).unwrap(),
+            last: NaiveDate::from_ymd_opt(2026, 4, 30).unwrap(),
+        };
+        let events = data_to_events(
+            &calendar,
+            &[],
+            &EventOptions::new(window, now(), &Config::default()),
+        )
+        .unwrap();
+        let days = group_by_day(events);
+        assert_eq!(
+            days.keys().collect::<Vec<_>>(),
+            ["2026-03-02", "2026-04-06"]
+        );
+    }
+
+    #[test]
+    fn test_batch_slices() {
+        let calendar = Calendar::from_str(include_str!("test-data/recurrence.ics")).unwrap();
+        let options = EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        let events = data_to_events(&calendar, &[], &options).unwrap();
+        let counts = parse_counts("1, 3").unwrap();
+        let slices = batch_slices(&events, &counts);
+        assert_eq!(slices["1"].len(), 1);
+        assert_eq!(slices["3"].len(), 3);
+        assert_eq!(slices["1"][0].id, slices["3"][0].id);
+        assert!(parse_counts("3,ten").is_err());
+        assert!(parse_counts("3,-1").is_err());
+        assert!(parse_counts("0").is_err());
+    }
+
+    #[test]
+    fn test_sort_ties() {
+        let end = now() + Days::new(1);
+        let events = [
+            ("b", "Sauna night", now() - Duration::from_secs(3600)),
+            ("a", "Sauna night", now() - Duration::from_secs(3600)),
+            ("c", "Board meeting", now() - Duration::from_secs(3600)),
+            ("d", "Sauna night", now() - Duration::from_secs(7200)),
+        ]
+        .map(|(uid, summary, start)| {
+            icalendar::Event::new()
+                .uid(uid)
+                .summary(summary)
+                .starts(start)
+                .ends(end)
+                .done()
+        });
+        for order in [[0, 1, 2, 3], [3, 2, 1, 0]] {
+            let mut calendar = Calendar::new();
+            for index in order {
+                calendar.push(events[index].clone());
+            }
+            let options =
+                EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+            let result = data_to_events(&calendar, &[], &options).unwrap();
+            let ids: Vec<&str> = result.iter().map(|event| event.id.as_str()).collect();
+            assert_eq!(ids, ["d", "c", "a", "b"]);
+        }
+    }
+
+    #[test]
+    fn test_source_order() {
+        let calendar = Calendar::from_str(include_str!("test-data/override.ics")).unwrap();
+        let mut options = EventOptions::new(
+            range((2026, 2, 1), (2026, 4, 30)),
+            now(),
+            &Config::default(),
+        );
+        options.sort = SortKey::Source;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        let starts: Vec<&str> = result
+            .iter()
+            .map(|event| event.start_iso8601.as_str())
+            .collect();
+        // The occurrences of the series come before the moved occurrence defined after it
+        assert_eq!(
+            starts,
+            [
+                "2026-02-03T16:00:00Z",
+                "2026-04-07T16:00:00Z",
+                "2026-03-05T16:00:00Z"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_days_until() {
+        let calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        let window = EventWindow::Past {
+            since: now() - Days::new(7),
+            until: now() + Days::new(7),
+        };
+        let result = data_to_events(
+            &calendar,
+            &[],
+            &EventOptions::new(window, now(), &Config::default()),
+        )
+        .unwrap();
+        // Most recently ended first
+        assert_matches!(
+            &result[..],
+            [Event { days_until: 1, .. }, Event { days_until: -1, .. }]
+        );
+    }
+
+    #[test]
+    fn test_multi_day() {
+        let day = NaiveDate::from_ymd_opt(2026, 2, 3).unwrap();
+        let timezone = Tz::Europe__Helsinki;
+        assert!(!is_multi_day(
+            &EventDate::Date(day),
+            &EventDate::Date(day + Days::new(1)),
+            timezone
+        ));
+        assert!(is_multi_day(
+            &EventDate::Date(day),
+            &EventDate::Date(day + Days::new(2)),
+            timezone
+        ));
+        let start = Utc.with_ymd_and_hms(2026, 2, 3, 12, 0, 0).unwrap();
+        let same_day = EventDate::DateTimeUtc(start + Duration::from_secs(3600));
+        let overnight = EventDate::DateTimeUtc(start + Days::new(1));
+        assert!(!is_multi_day(
+            &EventDate::DateTimeUtc(start),
+            &same_day,
+            timezone
+        ));
+        assert!(is_multi_day(
+            &EventDate::DateTimeUtc(start),
+            &overnight,
+            timezone
+        ));
+        // 21:00 to 23:00 UTC crosses midnight in Helsinki
+        let evening = Utc.with_ymd_and_hms(2026, 2, 3, 21, 0, 0).unwrap();
+        let late = EventDate::DateTimeUtc(evening + Duration::from_secs(2 * 3600));
+        assert!(!is_multi_day(
+            &EventDate::DateTimeUtc(evening),
+            &late,
+            Tz::UTC
+        ));
+        assert!(is_multi_day(
+            &EventDate::DateTimeUtc(evening),
+            &late,
+            timezone
+        ));
+    }
+
+    #[test]
+    fn test_in_progress() {
+        let start = EventDate::DateTimeUtc(now() - Duration::from_secs(60));
+        let end = EventDate::DateTimeUtc(now() + Duration::from_secs(60));
+        assert!(is_in_progress(&start, &end, now(), Tz::UTC));
+        assert!(!is_in_progress(
+            &start,
+            &end,
+            now() + Duration::from_secs(60),
+            Tz::UTC
+        ));
+        let today = now().date_naive();
+        let all_day = (
+            EventDate::Date(today),
+            EventDate::Date(today + Days::new(1)),
+        );
+        assert!(is_in_progress(&all_day.0, &all_day.1, now(), Tz::UTC));
+        assert!(!is_in_progress(
+            &all_day.0,
+            &all_day.1,
+            now() - Days::new(1),
+            Tz::UTC
+        ));
+    }
+
+    #[test]
+    fn test_between_window() {
+        let start = Utc.with_ymd_and_hms(2026, 2, 3, 12, 0, 0).unwrap();
+        let mut calendar = Calendar::new();
+        for (summary, hours) in [("First", 0), ("Second", 1), ("Third", 2)] {
+            let event_start = start + Duration::from_secs(hours * 3600);
+            calendar.push(
+                icalendar::Event::new()
+                    .summary(summary)
+                    .starts(event_start)
+                    .ends(event_start + Duration::from_secs(1800))
+                    .done(),
+            );
+        }
+        let summaries = |inclusive: Option<&str>| {
+            let query = BetweenQuery {
+                start: Some("2026-02-03T12:00:00Z".to_string()),
+                end: Some("2026-02-03T14:00:00+00:00".to_string()),
+                inclusive: inclusive
+                    .map(|inclusive| serde_json::from_value(inclusive.into()).unwrap()),
+            };
+            let options =
+                EventOptions::new(between_window(&query).unwrap(), now(), &Config::default());
+            data_to_events(&calendar, &[], &options)
+                .unwrap()
+                .into_iter()
+                .map(|event| event.summary)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summaries(None), ["First", "Second"]);
+        assert_eq!(summaries(Some("end")), ["Second", "Third"]);
+        assert_eq!(summaries(Some("both")), ["First", "Second", "Third"]);
+        assert_eq!(summaries(Some("none")), ["Second"]);
+
+        let reversed = BetweenQuery {
+            start: Some("2026-02-04T00:00:00Z".to_string()),
+            end: Some("2026-02-03T00:00:00Z".to_string()),
+            inclusive: None,
+        };
+        assert!(between_window(&reversed).is_err());
+    }
+
+    #[test]
+    fn test_fullcalendar_events() {
+        let calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        let query = FullCalendarQuery {
+            start: Some("2026-02-01T00:00:00+02:00".to_string()),
+            end: Some("2026-02-03".to_string()),
+        };
+        let window = fullcalendar_window(&query, now()).unwrap();
+        let options = EventOptions::new(window, now(), &Config::default());
+        let events = data_to_events(&calendar, &[], &options).unwrap();
+        let events: Vec<FullCalendarEvent> =
+            events.into_iter().map(FullCalendarEvent::from).collect();
+        assert_matches!(&events[..], [FullCalendarEvent { title, start, all_day: true, url: None, .. }]
+            if title == "Past Event" && start == "2026-02-01");
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["allDay"], true);
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let fetched = now();
+        assert_eq!(to_http_date(&fetched), "Mon, 02 Feb 2026 16:32:11 GMT");
+        assert!(is_not_modified(
+            Some("Mon, 02 Feb 2026 16:32:11 GMT"),
+            &fetched
+        ));
+        assert!(!is_not_modified(
+            Some("Mon, 02 Feb 2026 16:32:10 GMT"),
+            &fetched
+        ));
+        assert!(!is_not_modified(Some("yesterday"), &fetched));
+        assert!(!is_not_modified(None, &fetched));
+    }
+
+    #[test]
+    fn test_event_id() {
+        assert_eq!(
+            event_id(Some("abc@google.com"), None, "Sauna", "a", "b"),
+            "abc@google.com"
+        );
+        assert_eq!(
+            event_id(
+                Some("abc@google.com"),
+                Some("2026-02-02"),
+                "Sauna",
+                "a",
+                "b"
+            ),
+            "abc@google.com/2026-02-02"
+        );
+        let hashed = event_id(None, None, "Sauna", "2026-02-02", "2026-02-03");
+        assert_eq!(
+            hashed,
+            event_id(Some(""), None, "Sauna", "2026-02-02", "2026-02-03")
+        );
+        assert_ne!(
+            hashed,
+            event_id(None, None, "Sauna", "2026-02-03", "2026-02-04")
+        );
+        assert_eq!(hashed.len(), 16);
+    }
+
+    #[test]
+    fn test_description_truncation() {
+        let truncate = |text, max_chars| truncate_text(text, max_chars).0;
+        assert_eq!(
+            truncate_text("Sauna night", 20),
+            ("Sauna night".to_string(), false)
+        );
+        assert_eq!(
+            truncate_text("Sauna night", 11),
+            ("Sauna night".to_string(), false)
+        );
+        assert_eq!(
+            truncate_text("Sauna night", 7),
+            ("Sauna…".to_string(), true)
+        );
+        assert_eq!(truncate("Äänestys", 4), "Ään…");
+        assert_eq!(
+            truncate("Saunailta alkaa kello kuusi", 20),
+            "Saunailta alkaa…"
+        );
+        assert_eq!(
+            truncate_text("Tervetuloa saunaan. Ota pyyhe mukaan.", 30),
+            ("Tervetuloa saunaan.…".to_string(), true)
+        );
+        assert_eq!(
+            truncate("Katso linkkijkl.fi lisätietoja varten", 22),
+            "Katso linkkijkl.fi…"
+        );
+        assert_eq!(
+            DescriptionMode::None.apply(Some("Sauna night".to_string()), 300),
+            (None, false)
+        );
+    }
+
+    #[test]
+    fn test_week_window() {
+        let wednesday = NaiveDate::from_ymd_opt(2026, 12, 30).unwrap();
+        assert_matches!(
+            week_window(wednesday),
+            EventWindow::Range { first, last }
+                if first == NaiveDate::from_ymd_opt(2026, 12, 28).unwrap()
+                && last == NaiveDate::from_ymd_opt(2027, 1, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_month_window() {
+        assert_matches!(
+            month_window(2024, 2),
+            Ok(EventWindow::Range { first, last })
+                if first == NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()
+                && last == NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+        );
+        assert!(month_window(2024, 13).is_err());
+    }
+
+    #[test]
+    fn test_events_around() {
+        let calendar = Calendar::from_str(include_str!("test-data/recurrence.ics")).unwrap();
+        let mut options = EventOptions::new(
+            range((2026, 1, 1), (2026, 12, 31)),
+            now(),
+            &Config::default(),
+        );
+        options.sort = SortKey::Start;
+        options.around = Some(Around {
+            date: NaiveDate::from_ymd_opt(2026, 4, 6).unwrap(),
+            before: 1,
+            after: 2,
+        });
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        let starts: Vec<_> = result
+            .iter()
+            .map(|event| event.start_iso8601.as_str())
+            .collect();
+        assert_eq!(starts, ["2026-03-02", "2026-04-06", "2026-05-04"]);
+        // Counts beyond the events don't overflow
+        options.around = None;
+        let all = data_to_events(&calendar, &[], &options).unwrap().len();
+        options.around = Some(Around {
+            date: NaiveDate::from_ymd_opt(2026, 4, 6).unwrap(),
+            before: usize::MAX,
+            after: usize::MAX,
+        });
+        assert_eq!(data_to_events(&calendar, &[], &options).unwrap().len(), all);
+        assert_eq!(around_count("after", None, 100).unwrap(), 5);
+        assert!(around_count("after", Some(usize::MAX), 100).is_err());
+    }
+
+    #[test]
+    fn test_range_param_errors() {
+        let field = |from, to| {
+            let rejection = range_window(from, to, now(), Tz::UTC).unwrap_err();
+            rejection.find::<Error>().unwrap().field.clone()
+        };
+        assert_eq!(
+            field(Some("2026-03-01"), Some("2026-02-01")).as_deref(),
+            Some("from")
+        );
+        assert_eq!(field(None, Some("2026-01-01")).as_deref(), Some("to"));
+        assert_eq!(field(Some("0001-01-01"), None).as_deref(), Some("from"));
+        assert_eq!(
+            field(Some("2026-02-01"), Some("tomorrow")).as_deref(),
+            Some("to")
+        );
+        assert!(range_window(Some("2026-02-01"), Some("2026-02-01"), now(), Tz::UTC).is_ok());
+    }
+
+    #[test]
+    fn test_calendar_merging() {
+        let sources = [
+            CalendarSource {
+                url: "https://example.com/a.ics".to_string(),
+                name: Some("board".to_string()),
+                label: Some("Board".to_string()),
+                color: Some("#ff0000".to_string()),
+            },
+            CalendarSource {
+                url: "https://example.com/b.ics".to_string(),
+                name: None,
+                label: None,
+                color: None,
+            },
+        ];
+        let mut calendar = Calendar::new();
+        for source in &sources {
+            let source_calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+            merge_calendar(
+                &mut calendar,
+                source_calendar,
+                source,
+                &mut HashSet::new(),
+                DedupKey::Uid,
+            );
+        }
+        let options = EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [
+            Event { calendar_label: Some(label), calendar_color: Some(color), .. },
+            Event { calendar_label: None, calendar_color: None, .. },
+        ] if label == "Board" && color == "#ff0000");
+        assert_matches!(
+            calendar_info(&calendar),
+            CalendarInfo { name: Some(name), event_count: 4, .. } if name == "Test Calendar"
+        );
+
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        options.source = Some("board".to_string());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { calendar_label: Some(label), .. }] if label == "Board");
+    }
+
+    #[test]
+    fn test_merge_deduplication() {
+        let source = CalendarSource {
+            url: "https://example.com/a.ics".to_string(),
+            name: None,
+            label: None,
+            color: None,
+        };
+        let event = |uid: &str, summary: &str| {
+            icalendar::Event::new()
+                .uid(uid)
+                .summary(summary)
+                .all_day(now().date_naive() + Days::new(1))
+                .done()
+        };
+        let primary = || Calendar::new().push(event("a", "Sauna night")).done();
+        let secondary = || {
+            Calendar::new()
+                .push(event("a", "Sauna"))
+                .push(event("b", "Sauna night"))
+                .done()
+        };
+        let uids = |key| {
+            let mut calendar = Calendar::new();
+            let mut seen = HashSet::new();
+            for source_calendar in [primary(), secondary()] {
+                merge_calendar(&mut calendar, source_calendar, &source, &mut seen, key);
+            }
+            calendar
+                .events()
+                .filter_map(|event| event.get_uid().map(String::from))
+                .collect::<Vec<_>>()
+        };
+        // The event of the first calendar is kept
+        assert_eq!(uids(DedupKey::Uid), ["a", "b"]);
+        assert_eq!(uids(DedupKey::SummaryStart), ["a", "a"]);
+    }
+
+    #[test]
+    fn test_whitespace_normalization() {
+        assert_eq!(normalize_whitespace("  Sauna\r\n  night\t "), "Sauna night");
+        assert_eq!(normalize_whitespace(" \t"), "");
+        assert_eq!(
+            normalize_multiline("\n Agenda:  \n\n1.  Opening \n"),
+            "Agenda:\n\n1. Opening"
+        );
+    }
+
+    #[test]
+    fn test_localized_values() {
+        let calendar_data = include_str!("test-data/language.ics").to_string();
+        let calendar = process_calendar(calendar_data).unwrap();
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        for (language, expected) in [(Some("fi"), "Saunailta"), (Some("en"), "Sauna night")] {
+            options.language = language.map(String::from);
+            let result = data_to_events(&calendar, &[], &options).unwrap();
+            assert_matches!(&result[..], [Event {
+                summary,
+                location: Some(Location { string: location, .. }),
+                ..
+            }] if summary == expected && location == "Kuokkalan sauna");
+        }
+    }
+
+    #[test]
+    fn test_missing_values_serialized_as_null() {
+        let calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        let options = EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        let json = serde_json::to_value(&result[0]).unwrap();
+        for key in [
+            "created",
+            "calendar_label",
+            "recurrence_id",
+            "raw_properties",
+        ] {
+            assert_eq!(json.get(key), Some(&serde_json::Value::Null));
+        }
+    }
+
+    #[test]
+    fn test_field_selection() {
+        let calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        let options = EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        let events = data_to_events(&calendar, &[], &options).unwrap();
+        let fields = ["summary", "when", "unknown"].map(String::from);
+        let selected = select_fields(&events, &fields);
+        assert_eq!(
+            selected[0].as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["summary", "when"]
+        );
+    }
+
+    #[test]
+    fn test_hidden_events() {
+        let mut calendar = Calendar::from_str(include_str!("test-data/override.ics")).unwrap();
+        let hidden_uids = HashSet::from(["monthly-meeting@example.com".to_string()]);
+        remove_hidden_events(&mut calendar, &hidden_uids);
+        assert_eq!(calendar.events().count(), 0);
+    }
+
+    #[test]
+    fn test_json_api_document() {
+        let calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        let options = EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        let events = data_to_events(&calendar, &[], &options).unwrap();
+        let fields = ["summary", "id"].map(String::from);
+        let document = to_json_api(select_fields(&events, &fields));
+        let resource = &document["data"][0];
+        assert_eq!(resource["type"], "event");
+        assert_eq!(resource["id"], events[0].id);
+        assert_eq!(
+            resource["attributes"],
+            serde_json::json!({"summary": events[0].summary})
+        );
+    }
+
+    #[test]
+    fn test_calendar_decoding() {
+        assert_eq!(
+            decode_calendar(b"\xEF\xBB\xBFBEGIN:VCALENDAR", None),
+            "BEGIN:VCALENDAR"
+        );
+        assert_eq!(
+            decode_calendar(
+                b"SUMMARY:P\xE4\xE4t\xF6s",
+                Some("text/calendar; charset=\"ISO-8859-1\"")
+            ),
+            "SUMMARY:Päätös"
+        );
+        assert_eq!(
+            decode_calendar("SUMMARY:Päätös".as_bytes(), Some("text/calendar")),
+            "SUMMARY:Päätös"
+        );
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let clock = FixedClock(now());
+        assert_eq!(current_time(&clock, None, false).unwrap(), now());
+        // The debug header overrides the clock only in debug mode
+        let debug_now = "2026-06-01T12:00:00Z";
+        assert_eq!(current_time(&clock, Some(debug_now), false).unwrap(), now());
+        assert_eq!(
+            current_time(&clock, Some(debug_now), true).unwrap(),
+            DateTime::parse_from_rfc3339(debug_now).unwrap()
+        );
+        assert!(current_time(&clock, Some("tomorrow"), true).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_calendar_file() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test-data/basic.ics");
+        let calendar_data = fetch_calendar(&format!("file://{path}")).await;
+        assert!(
+            calendar_data.is_ok_and(|(data, modified)| data.contains("Test Event") && modified)
+        );
+        assert!(fetch_calendar("file:///nonexistent.ics").await.is_err());
+    }
+
+    #[test]
+    fn test_empty_calendar() {
+        assert!(non_empty_calendar(" \r\n".to_string()).is_err());
+        assert!(non_empty_calendar(include_str!("test-data/basic.ics").to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_series() {
+        let calendar = Calendar::from_str(include_str!("test-data/recurrence.ics")).unwrap();
+        let result = data_to_series(&calendar, now());
+        assert_matches!(&result[..], [Series { next_occurrence, .. }, ..]
+            if next_occurrence == "2026-03-02");
+    }
+
+    #[test]
+    fn test_rrule_description() {
+        assert_eq!(describe_rrule("FREQ=WEEKLY;BYDAY=TU"), "every Tuesday");
+        assert_eq!(
+            describe_rrule("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH"),
+            "every 2 weeks on Tuesday and Thursday"
+        );
+        assert_eq!(
+            describe_rrule("FREQ=MONTHLY;BYDAY=1MO"),
+            "every month on the first Monday"
+        );
+        assert_eq!(describe_rrule("FREQ=DAILY;INTERVAL=3"), "every 3 days");
+        assert_eq!(describe_rrule("BYDAY=MO"), "irregularly");
+    }
+
+    #[test]
+    fn test_raw_properties() {
+        let calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        options.raw = true;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { raw_properties: Some(raw), .. }]
+            if raw.keys().collect::<Vec<_>>() == ["TRANSP"] && raw["TRANSP"] == "TRANSPARENT");
+    }
+
+    #[test]
+    fn test_raw_times() {
+        let calendar = process_calendar(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:sauna@example.com\r\n\
+            SUMMARY:Sauna night\r\n\
+            DTSTART;TZID=\"Europe/Helsinki\":20260203T180000\r\n\
+            DTEND;TZID=Europe/Helsinki:20260203T210000\r\n\
+            BEGIN:VALARM\r\n\
+            ACTION:DISPLAY\r\n\
+            TRIGGER:-PT1H\r\n\
+            END:VALARM\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR"
+                .to_string(),
+        )
+        .unwrap();
+        let mut options =
+            EventOptions::new(range((2026, 2, 3), (2026, 2, 3)), now(), &Config::default());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(
+            &result[..],
+            [Event {
+                dtstart_raw: None,
+                dtend_raw: None,
+                ..
+            }]
+        );
+        options.raw_times = true;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { dtstart_raw: Some(start), dtend_raw: Some(end), .. }]
+            if start == "DTSTART;TZID=\"Europe/Helsinki\":20260203T180000"
+                && end == "DTEND;TZID=Europe/Helsinki:20260203T210000");
+        // Not listed among the unmapped properties
+        options.raw = true;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { raw_properties: Some(raw), .. }] if raw.is_empty());
+    }
+
+    /// Calendar of the given VEVENT properties, one per line
+    fn single_event_calendar(properties: &str) -> Calendar {
+        let lines: Vec<&str> = properties.lines().map(str::trim).collect();
+        let calendar_data = format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+            lines.join("\r\n")
+        );
+        Calendar::from_str(&calendar_data).unwrap()
+    }
+
+    fn range(first: (i32, u32, u32), last: (i32, u32, u32)) -> EventWindow {
+        EventWindow::Range {
+            first: NaiveDate::from_ymd_opt(first.0, first.1, first.2).unwrap(),
+            last: NaiveDate::from_ymd_opt(last.0, last.1, last.2).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_summary_fallback() {
+        let calendar = single_event_calendar(
+            "UID:block@example.com
+            DTSTART:20260203T160000Z
+            DTEND:20260203T170000Z",
+        );
+        let window = range((2026, 2, 3), (2026, 2, 3));
+        let mut options = EventOptions::new(window, now(), &Config::default());
+        assert!(data_to_events(&calendar, &[], &options).unwrap().is_empty());
+        options.summary_fallback = Some("(No title)".to_string());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { summary, .. }] if summary == "(No title)");
+    }
+
+    #[test]
+    fn test_leap_day_event() {
+        let calendar = single_event_calendar(
+            "UID:leap@example.com
+            SUMMARY:Leap day
+            DTSTART;VALUE=DATE:20280229
+            DTEND;VALUE=DATE:20280301",
+        );
+        let options = EventOptions::new(
+            range((2028, 2, 29), (2028, 2, 29)),
+            now(),
+            &Config::default(),
+        );
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { date: Some(date), duration_minutes: 1440, multi_day: false, .. }]
+            if date == "29/02/2028");
+        // Outside the leap day
+        let options =
+            EventOptions::new(range((2028, 3, 1), (2028, 3, 1)), now(), &Config::default());
+        assert!(data_to_events(&calendar, &[], &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_effective_end() {
+        let calendar = single_event_calendar(
+            "UID:weekend@example.com
+            SUMMARY:Weekend
+            DTSTART;VALUE=DATE:20260207
+            DTEND;VALUE=DATE:20260209",
+        );
+        let event = calendar.components[0].as_event().unwrap();
+        let last_day = NaiveDate::from_ymd_opt(2026, 2, 8).unwrap();
+        assert_matches!(effective_end(event), Some(EventDate::Date(date)) if date == last_day);
+        let options =
+            EventOptions::new(range((2026, 2, 8), (2026, 2, 8)), now(), &Config::default());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { date: Some(date), end_iso8601, multi_day: true, .. }]
+            if date == "07/02/2026 - 08/02/2026" && end_iso8601 == "2026-02-09");
+        // The exclusive end is not part of the event
+        let options =
+            EventOptions::new(range((2026, 2, 9), (2026, 2, 9)), now(), &Config::default());
+        assert!(data_to_events(&calendar, &[], &options).unwrap().is_empty());
+        // Timed ends are not adjusted
+        let calendar = single_event_calendar(
+            "UID:meeting@example.com
+            SUMMARY:Meeting
+            DTSTART:20260203T160000Z
+            DTEND:20260203T170000Z",
+        );
+        let event = calendar.components[0].as_event().unwrap();
+        assert_matches!(effective_end(event), Some(EventDate::DateTimeUtc(end))
+            if end == Utc.with_ymd_and_hms(2026, 2, 3, 17, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_leap_day_recurrence() {
+        let calendar = single_event_calendar(
+            "UID:leap@example.com
+            SUMMARY:Leap day
+            DTSTART;VALUE=DATE:20240229
+            DTEND;VALUE=DATE:20240301
+            RRULE:FREQ=YEARLY",
+        );
+        let options = EventOptions::new(
+            range((2025, 1, 1), (2029, 12, 31)),
+            now(),
+            &Config::default(),
+        );
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { start_iso8601, .. }] if start_iso8601 == "2028-02-29");
+    }
+
+    #[test]
+    fn test_new_years_eve_event() {
+        let calendar = single_event_calendar(
+            "UID:nye@example.com
+            SUMMARY:New Year's Eve party
+            DTSTART:20261231T200000Z
+            DTEND:20270101T020000Z",
+        );
+        // The event is within both years
+        for window in [
+            range((2026, 12, 31), (2026, 12, 31)),
+            range((2027, 1, 1), (2027, 1, 1)),
+        ] {
+            let options = EventOptions::new(window, now(), &Config::default());
+            let result = data_to_events(&calendar, &[], &options).unwrap();
+            assert_matches!(
+                &result[..],
+                [Event {
+                    duration_minutes: 360,
+                    multi_day: true,
+                    ..
+                }]
+            );
+        }
+        let calendar = single_event_calendar(
+            "UID:nye@example.com
+            SUMMARY:New Year's Eve
+            DTSTART;VALUE=DATE:20261231
+            DTEND;VALUE=DATE:20270101
+            RRULE:FREQ=YEARLY;COUNT=2",
+        );
+        let options = EventOptions::new(
+            range((2027, 1, 1), (2027, 12, 31)),
+            now(),
+            &Config::default(),
+        );
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { start_iso8601, end_iso8601, .. }]
+            if start_iso8601 == "2027-12-31" && end_iso8601 == "2028-01-01");
+    }
+
+    #[test]
+    fn test_nonexistent_local_time() {
+        // Clocks in Helsinki skip from 03:00 to 04:00 on 29 March 2026
+        let date_time = DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone {
+            date_time: NaiveDate::from_ymd_opt(2026, 3, 29)
+                .unwrap()
+                .and_hms_opt(3, 30, 0)
+                .unwrap(),
+            tzid: "Europe/Helsinki".to_string(),
+        });
+        assert_matches!(to_event_date(date_time), Some(EventDate::DateTimeUtc(date_time))
+            if date_time.to_rfc3339() == "2026-03-29T01:30:00+00:00");
+    }
+
+    #[test]
+    fn test_stale_calendar_max_age() {
+        let checked = now() - chrono::Duration::hours(2);
+        assert!(is_servable_stale(checked, now(), None));
+        assert!(is_servable_stale(
+            checked,
+            now(),
+            Some(Duration::from_secs(3 * 3600))
+        ));
+        assert!(!is_servable_stale(
+            checked,
+            now(),
+            Some(Duration::from_secs(3600))
+        ));
+    }
+
+    #[test]
+    fn test_category_color() {
+        let colors = HashMap::from([("party".to_string(), "#f00".to_string())]);
+        assert_eq!(category_color("Party", &colors), "#f00");
+        let derived = category_color("Meeting", &colors);
+        assert_eq!(derived, category_color("meeting", &HashMap::new()));
+        assert_matches!(derived.as_bytes(), [b'#', rest @ ..] if rest.len() == 6);
+        assert_eq!(hsl_to_hex(0.0, 1.0, 0.5), "#ff0000");
+        assert_eq!(hsl_to_hex(240.0, 1.0, 0.5), "#0000ff");
+    }
+
+    #[test]
+    fn test_public_addresses() {
+        for address in [
+            "93.184.215.14",
+            "198.20.0.1",
+            "2606:2800:21f:cb07:6820:80da:af6b:8b2c",
+            "64:ff9b::93.184.215.14",
+            "2002:5db8:d70e::1",
+        ] {
+            assert!(is_public_address(address.parse().unwrap()), "{address}");
+        }
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.1.1",
+            "172.16.0.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::10.0.0.1",
+            "2002:c0a8:101::1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "240.0.0.1",
+            "255.255.255.254",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_without_background_refresh() {
+        let config = Config {
+            background_refresh: None,
+            ..Config::default()
+        };
+        let rejection = stream_events(now(), Arc::new(config)).await.err().unwrap();
+        assert_eq!(
+            rejection.find::<Error>().unwrap().status,
+            Some(StatusCode::NOT_FOUND)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_query_when_not_modified() {
+        let query = serde_json::from_value(serde_json::json!({ "from": "someday" })).unwrap();
+        let if_modified_since = Some("Fri, 01 Jan 2100 00:00:00 GMT".to_string());
+        let rejection = events_response(query, if_modified_since, None, now(), &Config::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            rejection.find::<Error>().unwrap().status,
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_fetch_error_length() {
+        remember_fetch_error("backtrace ".repeat(10_000));
+        let last_error = LAST_FETCH_ERROR.lock().unwrap().clone().unwrap();
+        assert!(last_error.message.chars().count() <= MAX_FETCH_ERROR_CHARS);
+        assert!(last_error.message.ends_with('…'));
+    }
+
+    #[test]
+    fn test_health_report() {
+        let report = health_report(None, None, now(), Duration::from_secs(600));
+        assert_eq!(report.status, "unavailable");
+        let data = CalendarData {
+            calendar: Arc::new(Calendar::from_str(include_str!("test-data/basic.ics")).unwrap()),
+            spaces: vec![],
+            fetched: now() - chrono::Duration::hours(1),
+            checked: now() - chrono::Duration::minutes(5),
+        };
+        let report = health_report(Some(&data), None, now(), Duration::from_secs(600));
+        assert_eq!(report.status, "ok");
+        assert_eq!(report.cache_age_secs, Some(300));
+        assert_eq!(report.event_count, Some(2));
+        let report = health_report(Some(&data), None, now(), Duration::from_secs(60));
+        assert_eq!(report.status, "stale");
+    }
+
+    #[test]
+    fn test_description_link() {
+        assert_eq!(
+            first_link("Sign up at https://example.com/signup?id=1. See you!").as_deref(),
+            Some("https://example.com/signup?id=1")
+        );
+        assert_eq!(
+            first_link("<a href=\"http://example.com\">form</a>").as_deref(),
+            Some("http://example.com")
+        );
+        assert_eq!(first_link("No links here"), None);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let calendar = Calendar::from_str(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n\
+            ATTACH:https://example.com/agenda.pdf\r\n\
+            ATTACH;ENCODING=BASE64;VALUE=BINARY:SGVsbG8=\r\n\
+            END:VEVENT\r\nEND:VCALENDAR\r\n",
+        )
+        .unwrap();
+        let event = calendar.events().next().unwrap();
+        assert_eq!(event_attachments(event), ["https://example.com/agenda.pdf"]);
+    }
+
+    #[test]
+    fn test_webcal_url_normalization() {
+        assert_eq!(
+            normalize_calendar_url("webcal://example.com/basic.ics"),
+            "https://example.com/basic.ics"
+        );
+        assert_eq!(
+            normalize_calendar_url("webcals://example.com/basic.ics"),
+            "https://example.com/basic.ics"
+        );
+        assert_eq!(
+            normalize_calendar_url("https://example.com/basic.ics"),
+            "https://example.com/basic.ics"
+        );
+    }
+
+    #[test]
+    fn test_location_filter() {
+        let mut calendar = Calendar::new();
+        for (summary, location) in [
+            ("Sauna night", Some("Clubroom, Agora")),
+            ("Excursion", None),
+        ] {
+            let mut event = icalendar::Event::new();
+            event
+                .summary(summary)
+                .all_day(now().date_naive() + Days::new(1));
+            if let Some(location) = location {
+                event.location(location);
+            }
+            calendar.push(event.done());
+        }
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        options.location = Some("clubROOM".to_string());
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { summary, .. }] if summary == "Sauna night");
+        options.location = Some("Lecture hall".to_string());
+        assert!(data_to_events(&calendar, &[], &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_event_kind_filter() {
+        let mut calendar = Calendar::from_str(include_str!("test-data/basic.ics")).unwrap();
+        calendar.extend(
+            Calendar::from_str(include_str!("test-data/duration.ics"))
+                .unwrap()
+                .components,
+        );
+        let mut options =
+            EventOptions::new(EventWindow::Upcoming(now()), now(), &Config::default());
+        assert_eq!(data_to_events(&calendar, &[], &options).unwrap().len(), 2);
+        options.kind = EventKind::Timed;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { summary, .. }] if summary == "Duration Event");
+        options.kind = EventKind::AllDay;
+        let result = data_to_events(&calendar, &[], &options).unwrap();
+        assert_matches!(&result[..], [Event { summary, .. }] if summary == "Test Event");
+    }
+
+    #[test]
+    fn test_location_url() {
+        assert_eq!(
+            url_for_location(
+                "Ag C231",
+                &[],
+                false,
+                "https://osm.org/search?query={query}"
+            ),
+            "https://osm.org/search?query=Ag%20C231"
+        );
+    }
+
+    #[test]
+    fn test_address_cleanup() {
+        assert_eq!(
+            clean_address("Kuokkalan sauna, Kuokkalantie 5, 40520 Jyväskylä"),
+            "Kuokkalantie 5, 40520 Jyväskylä"
+        );
+        assert_eq!(
+            clean_address("Kuokkalantie 5,  40520 Jyväskylä"),
+            "Kuokkalantie 5, 40520 Jyväskylä"
+        );
+        assert_eq!(clean_address("Ag C231"), "Ag C231");
+    }
+
+    #[test]
+    fn test_recurrence_parsing() {
+        let calendar_data: &'static str = include_str!("test-data/recurrence.ics");
+        let now = now();
+        let calendar = Calendar::from_str(calendar_data).unwrap();
+        let result = data_to_events(
+            &calendar,
+            &[],
+            &EventOptions::new(EventWindow::Upcoming(now), now, &Config::default()),
+        )
+        .unwrap();
         //result.iter().for_each(|event| println!("{}", event.date)); // debug print
         assert_matches!(
             &result[..],
             [
                 Event {
                     summary,
-                    date: date1,
+                    date: Some(date1),
                     location: None,
                     description: None,
+                    recurring: true,
+                    recurrence_id: Some(recurrence_id),
                     ..
                 },
                 Event {
-                    date: date2,
+                    date: Some(date2),
                     ..
                 },
                 Event {
-                    date: date3,
+                    date: Some(date3),
                     ..
                 },
                 Event {
-                    date: date4,
+                    date: Some(date4),
                     ..
                 },
                 Event {
-                    date: date5,
+                    date: Some(date5),
                     ..
                 }, .. ,
                 Event {
-                    date: last_date,
+                    date: Some(last_date),
                     ..
                 }
-            ] if date1 == "02/02/2026" // Each event comforms correctly to recurrence rules
+            ] if recurrence_id == "2026-02-02"
+                && date1 == "02/02/2026" // Each event comforms correctly to recurrence rules
                 && date2 == "02/03/2026"
                 && date3 == "06/04/2026"
                 && date4 == "04/05/2026"
                 && date5 == "06/07/2026" // Skipped one event because of exclusion rules
-                && last_date == "04/01/2027" // The last returned date is not older than a year
+                && last_date == "01/02/2027" // The last returned date ends within a year
         )
     }
 }
@@ -1,59 +1,464 @@
-#![feature(assert_matches)]
-
 use serde::Serialize;
 use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
 use warp::Filter;
-use warp::http::StatusCode;
-use warp::{Rejection, Reply};
+use warp::filters::path::FullPath;
+use warp::http::{HeaderValue, Method, StatusCode};
+use warp::{Rejection, Reply, reject};
 
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
 use crate::types::Error;
 
+mod clock;
+mod config;
 mod events;
+mod signing;
 pub mod types;
 
 /// An API error serializable to JSON.
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct ErrorMessage {
     code: u16,
     message: String,
+    /// Method of the failed request, filled in by `with_request_details`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    /// Path of the failed request, filled in by `with_request_details`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    /// Request parameter which was invalid, for highlighting it in forms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    /// Available endpoints, pointing clients of unknown paths to the right ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoints: Option<&'static [&'static str]>,
+}
+
+/// Paths of the available endpoints, listed in the discovery document
+const ENDPOINTS: &[&str] = &[
+    "/events",
+    "/events.json",
+    "/events/range/<from>/<to>",
+    "/events/past",
+    "/events/agenda",
+    "/events/by-category",
+    "/events/histogram",
+    "/events/index",
+    "/events/batch",
+    "/events/fullcalendar",
+    "/events/between",
+    "/events/stream",
+    "/events/week",
+    "/events/week/next",
+    "/events/month/<year>/<month>",
+    "/events/preview",
+    "/series",
+    "/health",
+    "/health/ready",
+    "/calendar/info",
+];
+
+/// Discovery document served at the root path.
+#[derive(Serialize)]
+struct ServiceInfo {
+    name: &'static str,
+    version: &'static str,
+    endpoints: &'static [&'static str],
+}
+
+fn service_info() -> impl Reply {
+    warp::reply::json(&ServiceInfo {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        endpoints: ENDPOINTS,
+    })
+}
+
+/// Restricts routes to read-only requests without a body.
+fn read_only() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .or(warp::options())
+        .unify()
+        .and(warp::header::optional::<u64>("content-length"))
+        .and_then(|content_length: Option<u64>| async move {
+            match content_length {
+                Some(length) if length > 0 => Err(reject::custom(Error {
+                    message: "413 - Request body is not accepted".to_string(),
+                    status: Some(StatusCode::PAYLOAD_TOO_LARGE),
+                    ..Default::default()
+                })),
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+}
+
+/// Whether the path is the root or one of `ENDPOINTS`, whose `<placeholders>` match any segment.
+fn is_endpoint(path: &str) -> bool {
+    path == "/"
+        || ENDPOINTS.iter().any(|endpoint| {
+            let mut segments = path.split('/');
+            let mut endpoint_segments = endpoint.split('/');
+            loop {
+                match (segments.next(), endpoint_segments.next()) {
+                    (None, None) => return true,
+                    (Some(segment), Some(pattern))
+                        if segment == pattern
+                            || (pattern.starts_with('<') && !segment.is_empty()) => {}
+                    _ => return false,
+                }
+            }
+        })
+}
+
+/// Canonical form of the path, lowercase with a single leading slash and without a trailing
+/// one, when it differs from the path and names an endpoint.
+fn canonical_path(path: &str) -> Option<String> {
+    // Leading slashes are collapsed, as `//host` would redirect to another site
+    let canonical = format!("/{}", path.trim_matches('/').to_lowercase());
+    (canonical != path && is_endpoint(&canonical)).then_some(canonical)
+}
+
+/// Redirects paths of endpoints with extra slashes or uppercase letters permanently to their
+/// canonical form, keeping the query string.
+fn canonical_path_redirect() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(
+            warp::query::raw()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and_then(|path: FullPath, query: Option<String>| async move {
+            let canonical = canonical_path(path.as_str()).ok_or_else(reject::not_found)?;
+            let location = match query {
+                Some(query) => format!("{canonical}?{query}"),
+                None => canonical,
+            };
+            let location = HeaderValue::from_str(&location).map_err(|_| reject::not_found())?;
+            let reply = warp::reply::with_header(warp::reply(), "Location", location);
+            Ok::<_, Rejection>(warp::reply::with_status(
+                reply,
+                StatusCode::MOVED_PERMANENTLY,
+            ))
+        })
+}
+
+/// Routes of the service. Readiness probes and the discovery document are served without a
+/// signature even when `FEED_SIGNING_KEY` is set.
+fn routes(
+    config: Arc<Config>,
+    clock: Arc<dyn Clock>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    read_only().and(
+        canonical_path_redirect()
+            .or(events::readiness_filter(config.clone()))
+            .or(signing::require_signature(config.clone()).and(events::filter(config, clock)))
+            .or(warp::path::end().map(service_info)),
+    )
+}
+
+/// Adds the CORS headers allowed for the request origin.
+fn with_cors_headers(
+    reply: impl Reply,
+    origin: Option<&str>,
+    config: &Config,
+) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    let headers = response.headers_mut();
+    if let Some(allowed_origin) = config
+        .allowed_origin(origin)
+        .and_then(|origin| HeaderValue::from_str(&origin).ok())
+    {
+        headers.insert("Access-Control-Allow-Origin", allowed_origin);
+        if config.cors_credentials {
+            headers.insert(
+                "Access-Control-Allow-Credentials",
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+    if config.cors_credentials {
+        // The response depends on the origin, thus shared caches must keep them apart
+        headers.append("Vary", HeaderValue::from_static("Origin"));
+    }
+    response
+}
+
+/// Adds the request method and path to error responses, which the rejection handler can't see.
+fn with_request_details(
+    mut response: warp::reply::Response,
+    method: &Method,
+    path: &FullPath,
+) -> warp::reply::Response {
+    if let Some(error_message) = response.extensions_mut().remove::<ErrorMessage>() {
+        let error_message = ErrorMessage {
+            method: Some(method.to_string()),
+            path: Some(path.as_str().to_string()),
+            ..error_message
+        };
+        if let Ok(body) = serde_json::to_vec(&error_message) {
+            *response.body_mut() = body.into();
+        }
+    }
+    response
+}
+
+/// Declares the UTF-8 charset of textual responses, which some older clients otherwise
+/// decode in another charset, mangling e.g. Finnish characters.
+fn with_utf8_charset(mut response: warp::reply::Response) -> warp::reply::Response {
+    let content_type = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|content_type| content_type.to_str().ok());
+    if let Some(content_type) = content_type
+        && is_textual(content_type)
+        && !content_type.to_ascii_lowercase().contains("charset=")
+        && let Ok(value) = HeaderValue::from_str(&format!("{content_type}; charset=utf-8"))
+    {
+        response.headers_mut().insert("Content-Type", value);
+    }
+    response
+}
+
+/// Whether the media type is text, including JSON, e.g. `application/vnd.api+json`
+fn is_textual(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    media_type.starts_with("text/")
+        || media_type == "application/json"
+        || media_type.ends_with("+json")
+}
+
+/// Logs one line per request, including rejected requests.
+fn log_access(
+    target: &str,
+    start: Instant,
+    method: &Method,
+    path: &FullPath,
+    response: &warp::reply::Response,
+) {
+    // Streamed bodies have no known size
+    let bytes = http_body::Body::size_hint(response.body())
+        .exact()
+        .map_or_else(|| "-".to_string(), |bytes| bytes.to_string());
+    println!(
+        "{target}: method={method} path={} status={} bytes={bytes} duration_ms={}",
+        path.as_str(),
+        response.status().as_u16(),
+        start.elapsed().as_millis(),
+    );
 }
 
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let code;
     let message;
+    let mut retry_after = None;
+    let mut endpoints = None;
+    let mut field = None;
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
         message = "404 - Not found";
+        endpoints = Some(ENDPOINTS);
     } else if let Some(error) = err.find::<Error>() {
         eprintln!(
             "{}",
             serde_json::to_string_pretty(&error).unwrap_or_default()
         );
-        code = StatusCode::INTERNAL_SERVER_ERROR;
+        code = error.status.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
         message = &error.message;
+        retry_after = error.retry_after;
+        field = error.field.clone();
+    } else if let Some(error) = err.find::<reject::InvalidQuery>() {
+        // E.g. a negative or non-numeric value for a numeric parameter
+        eprintln!("invalid query: {error}");
+        code = StatusCode::BAD_REQUEST;
+        message = "400 - Invalid query parameters";
+    } else if err.find::<reject::MethodNotAllowed>().is_some() {
+        code = StatusCode::METHOD_NOT_ALLOWED;
+        message = "405 - Method not allowed";
     } else {
         eprintln!("unhandled rejection: {:?}", err);
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "500 - Internal server error";
     }
-    let json = warp::reply::json(&ErrorMessage {
+    let error_message = ErrorMessage {
         code: code.as_u16(),
         message: message.into(),
-    });
+        method: None,
+        path: None,
+        field,
+        endpoints,
+    };
+    let json = warp::reply::json(&error_message);
 
-    Ok(warp::reply::with_status(json, code))
+    let mut response = warp::reply::with_status(json, code).into_response();
+    response.extensions_mut().insert(error_message);
+    if let Some(retry_after) = retry_after {
+        response
+            .headers_mut()
+            .insert("Retry-After", HeaderValue::from(retry_after));
+    }
+    Ok(response)
 }
 
 #[tokio::main]
 async fn main() {
-    let routes = warp::any()
-        .and(events::filter())
-        .or(warp::path::end().map(|| "Hello world!"))
-        .map(|reply| {
-            warp::reply::with_header(reply, "Access-Control-Allow-Origin", "*")
+    let config = match Config::from_env() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("invalid configuration: {err:#}");
+            std::process::exit(1);
+        }
+    };
+
+    // Helper for admin tooling: `linkki-web-api sign <path>[?<query>] <valid_secs>`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command, path, valid_secs] = &args[..]
+        && command == "sign"
+    {
+        let exp = valid_secs
+            .parse::<i64>()
+            .ok()
+            .and_then(|valid_secs| chrono::Utc::now().timestamp().checked_add(valid_secs));
+        let (Some(key), Some(exp)) = (&config.feed_signing_key, exp) else {
+            eprintln!(
+                "usage: FEED_SIGNING_KEY=<key> linkki-web-api sign <path>[?<query>] <valid_secs>"
+            );
+            std::process::exit(1);
+        };
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let sig = signing::sign(key, path, query, exp);
+        match query {
+            "" => println!("{path}?exp={exp}&sig={sig}"),
+            query => println!("{path}?{query}&exp={exp}&sig={sig}"),
+        }
+        return;
+    }
+
+    println!("Starting with configuration: {config}");
+    for warning in config.warnings() {
+        eprintln!("warning: {warning}");
+    }
+    events::init(&config);
+
+    if let Some(interval) = config.background_refresh {
+        let config = config.clone();
+        tokio::spawn(async move {
+            // The first tick completes immediately, warming the cache at startup
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                events::refresh_calendar_data(&config).await;
+            }
+        });
+    }
+
+    let cors_config = config.clone();
+    let routes = routes(config.clone(), Arc::new(SystemClock))
+        .and(warp::header::optional::<String>("origin"))
+        .map(move |reply, origin: Option<String>| {
+            with_cors_headers(reply, origin.as_deref(), &cors_config)
         })
-        .recover(handle_rejection);
+        .recover(handle_rejection)
+        .map(Reply::into_response);
+    let access_log_config = config.clone();
+    let routes = warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(routes)
+        .map(
+            move |start: Instant, method: Method, path: FullPath, reply: warp::reply::Response| {
+                let reply = with_utf8_charset(with_request_details(reply, &method, &path));
+                log_access(
+                    &access_log_config.access_log_target,
+                    start,
+                    &method,
+                    &path,
+                    &reply,
+                );
+                reply
+            },
+        );
 
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+    warp::serve(routes).run(config.address).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_type(response: &warp::reply::Response) -> &str {
+        response.headers()["Content-Type"].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_readiness_without_signature() {
+        let config = Arc::new(Config {
+            feed_signing_key: Some("secret".to_string()),
+            // Keeps the probe from fetching the calendar
+            background_refresh: Some(std::time::Duration::from_secs(60)),
+            ..Config::default()
+        });
+        let routes = routes(config, Arc::new(SystemClock)).recover(handle_rejection);
+        let ready = warp::test::request()
+            .path("/health/ready")
+            .reply(&routes)
+            .await;
+        assert_eq!(ready.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready.body(), r#"{"ready":false}"#);
+        let events = warp::test::request().path("/events").reply(&routes).await;
+        assert_eq!(events.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        assert_eq!(canonical_path("/Events/"), Some("/events".to_string()));
+        assert_eq!(
+            canonical_path("/events/range/2026-02-01/2026-02-28/"),
+            Some("/events/range/2026-02-01/2026-02-28".to_string())
+        );
+        assert_eq!(canonical_path("//"), Some("/".to_string()));
+        assert_eq!(canonical_path("/events"), None);
+        assert_eq!(canonical_path("/"), None);
+        // Protocol relative paths must not redirect to other sites
+        assert_eq!(canonical_path("//evil.com/"), None);
+        assert_eq!(canonical_path("//events"), Some("/events".to_string()));
+        // Unknown paths are left to the 404 handler
+        assert_eq!(canonical_path("/Unknown/"), None);
+        assert_eq!(canonical_path("/events/range//"), None);
+    }
+
+    #[test]
+    fn test_utf8_charset() {
+        let json = with_utf8_charset(warp::reply::json(&"Kokous").into_response());
+        assert_eq!(content_type(&json), "application/json; charset=utf-8");
+        let json_api = with_utf8_charset(
+            warp::reply::with_header(warp::reply(), "Content-Type", "application/vnd.api+json")
+                .into_response(),
+        );
+        assert_eq!(
+            content_type(&json_api),
+            "application/vnd.api+json; charset=utf-8"
+        );
+        let declared = with_utf8_charset(
+            warp::reply::with_header(warp::reply(), "Content-Type", "text/plain; charset=UTF-8")
+                .into_response(),
+        );
+        assert_eq!(content_type(&declared), "text/plain; charset=UTF-8");
+        let binary = with_utf8_charset(
+            warp::reply::with_header(warp::reply(), "Content-Type", "image/png").into_response(),
+        );
+        assert_eq!(content_type(&binary), "image/png");
+    }
 }
@@ -0,0 +1,225 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use warp::filters::path::FullPath;
+use warp::http::StatusCode;
+use warp::{Filter, reject};
+
+use crate::config::Config;
+use crate::types::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Query parameters of a signed URL
+#[derive(Deserialize)]
+struct SignatureQuery {
+    /// Hex encoded HMAC-SHA256 of the path, expiry and other query parameters
+    sig: Option<String>,
+    /// Unix timestamp after which the URL is no longer valid
+    exp: Option<i64>,
+}
+
+/// Query parameters other than `sig` and `exp`, decoded, sorted and encoded again, so that
+/// the signature doesn't depend on their order or encoding
+fn canonical_query(query: &str) -> String {
+    let decode = |component: &str| {
+        urlencoding::decode(&component.replace('+', " "))
+            .map(|decoded| decoded.into_owned())
+            .unwrap_or_else(|_| component.to_string())
+    };
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            (decode(name), decode(value))
+        })
+        .filter(|(name, _)| name != "sig" && name != "exp")
+        .collect();
+    params.sort();
+    params
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}",
+                urlencoding::encode(name),
+                urlencoding::encode(value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn mac(key: &str, path: &str, query: &str, exp: i64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{path}\n{exp}\n{}", canonical_query(query)).as_bytes());
+    mac
+}
+
+/// Signature of the path with the query parameters valid until `exp`, e.g. for links
+/// generated by admin tooling
+pub fn sign(key: &str, path: &str, query: &str, exp: i64) -> String {
+    mac(key, path, query, exp)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Whether the signature of the path and query is valid and unexpired, compared in constant time
+fn verify(key: &str, path: &str, query: &str, exp: i64, sig: &str, now: i64) -> bool {
+    if exp < now {
+        return false;
+    }
+    match decode_hex(sig) {
+        Some(sig) => mac(key, path, query, exp).verify_slice(&sig).is_ok(),
+        None => false,
+    }
+}
+
+/// Rejects requests without a valid signature with 403 when `FEED_SIGNING_KEY` is set
+pub fn require_signature(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::query::<SignatureQuery>())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(
+            move |path: FullPath, query: SignatureQuery, raw_query: String| {
+                let config = config.clone();
+                async move {
+                    let Some(key) = &config.feed_signing_key else {
+                        return Ok(());
+                    };
+                    let now = chrono::Utc::now().timestamp();
+                    match (query.sig, query.exp) {
+                        (Some(sig), Some(exp))
+                            if verify(key, path.as_str(), &raw_query, exp, &sig, now) =>
+                        {
+                            Ok(())
+                        }
+                        _ => Err(reject::custom(Error {
+                            message: "403 - The link is invalid or has expired".to_string(),
+                            status: Some(StatusCode::FORBIDDEN),
+                            ..Default::default()
+                        })),
+                    }
+                }
+            },
+        )
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_verification() {
+        let sig = sign("secret", "/events", "", 1_800_000_000);
+        let verify_events =
+            |path, query, exp, sig, now| verify("secret", path, query, exp, sig, now);
+        assert!(verify_events(
+            "/events",
+            "",
+            1_800_000_000,
+            &sig,
+            1_700_000_000
+        ));
+        // The signature itself is not part of the signed query
+        let query = format!("exp=1800000000&sig={sig}");
+        assert!(verify_events(
+            "/events",
+            &query,
+            1_800_000_000,
+            &sig,
+            1_700_000_000
+        ));
+        // Expired
+        assert!(!verify_events(
+            "/events",
+            "",
+            1_800_000_000,
+            &sig,
+            1_800_000_001
+        ));
+        // Signed for another path, expiry or key
+        assert!(!verify_events(
+            "/events/past",
+            "",
+            1_800_000_000,
+            &sig,
+            1_700_000_000
+        ));
+        assert!(!verify_events(
+            "/events",
+            "",
+            1_900_000_000,
+            &sig,
+            1_700_000_000
+        ));
+        assert!(!verify(
+            "other",
+            "/events",
+            "",
+            1_800_000_000,
+            &sig,
+            1_700_000_000
+        ));
+        assert!(!verify_events(
+            "/events",
+            "",
+            1_800_000_000,
+            "zz",
+            1_700_000_000
+        ));
+    }
+
+    #[test]
+    fn test_signed_query() {
+        let sig = sign("secret", "/events", "source=club&lang=fi", 1_800_000_000);
+        // Parameters in another order or encoding
+        for query in [
+            "source=club&lang=fi",
+            "lang=fi&exp=1800000000&source=club",
+            "lang=%66i&source=club&sig=0",
+        ] {
+            assert!(verify(
+                "secret",
+                "/events",
+                query,
+                1_800_000_000,
+                &sig,
+                1_700_000_000
+            ));
+        }
+        // Changed, added or removed parameters
+        for query in [
+            "source=other&lang=fi",
+            "source=club&lang=fi&raw=true",
+            "lang=fi",
+        ] {
+            assert!(!verify(
+                "secret",
+                "/events",
+                query,
+                1_800_000_000,
+                &sig,
+                1_700_000_000
+            ));
+        }
+    }
+}
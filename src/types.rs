@@ -1,4 +1,5 @@
 use serde::Serialize;
+use warp::http::StatusCode;
 use warp::reject;
 
 /// Error type, which can partially get sent to user
@@ -8,6 +9,35 @@ pub struct Error {
     pub message: String,
     /// The bit that gets printed to logs, but not to user
     pub details: Option<String>,
+    /// Status code of the response, internal server error when unset
+    #[serde(skip)]
+    pub status: Option<StatusCode>,
+    /// Seconds after which the client may retry, sent as `Retry-After`
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+    /// Name of the request parameter which was invalid
+    pub field: Option<String>,
+}
+
+impl Error {
+    /// Error caused by an invalid request
+    pub fn bad_request(message: &str) -> Self {
+        Error {
+            message: message.to_string(),
+            details: None,
+            status: Some(StatusCode::BAD_REQUEST),
+            retry_after: None,
+            field: None,
+        }
+    }
+
+    /// Error caused by an invalid value of the named request parameter
+    pub fn invalid_param(field: &str, message: &str) -> Self {
+        Error {
+            field: Some(field.to_string()),
+            ..Error::bad_request(message)
+        }
+    }
 }
 
 impl reject::Reject for Error {}